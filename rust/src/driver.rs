@@ -4,16 +4,97 @@
 //! Drivers load and save key value pairs from and to other objects
 //! or external media.
 //!
-//! TODO next: FileDriver
-//!
+
+pub mod binary;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub mod compressed;
+pub mod directory;
+pub mod dotenv;
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+pub mod file;
+pub mod include;
+pub mod ini;
+pub mod json;
+pub mod log;
+pub mod medium;
+pub mod properties;
+mod quote;
+pub mod signed;
+pub mod toml;
 
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::path::Path;
+
+pub use self::binary::{BinaryDriver, BinaryFormat};
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use self::compressed::{CompressedDriver, CompressedFormat, Compression};
+pub use self::directory::DirectoryDriver;
+pub use self::dotenv::{DotenvDriver, DotenvFormat};
+#[cfg(feature = "encryption")]
+pub use self::encrypted::{EncryptedDriver, EncryptedFormat, EncryptionError, Key};
+pub use self::file::{FileDriver, PermissionPolicy, TextFormat, WarningHandler};
+pub use self::include::IncludeDriver;
+pub use self::ini::{Duplicates, IniDriver, IniFormat};
+pub use self::json::{JsonDriver, JsonFormat};
+pub use self::log::LogDriver;
+pub use self::medium::{FileMedium, FormatDriver, Medium, MemoryMedium, SocketMedium, StdioMedium};
+pub use self::properties::{PropertiesDriver, PropertiesFormat};
+pub use self::signed::{Integrity, IntegrityError, SignedDriver, SignedFormat};
+pub use self::toml::{TomlDriver, TomlFormat};
+
+use redact::Redact;
 
 pub type Map = HashMap<String, String>;
 pub type IOResult = io::Result<HashMap<String, String>>;
 pub type IOCheck = io::Result<()>;
 
+/// How an entry has changed since the store was last loaded or saved.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Changed,
+    Removed
+}
+
+/// Keys changed since the store was last loaded or saved.
+///
+pub type Changes = HashMap<String, Change>;
+
+/// A single write to apply to a driver.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Put(String, String),
+    Delete(String)
+}
+
+/// Writes to apply to a driver in order.
+///
+pub type Batch = Vec<Op>;
+
+/// Converts a set of changes into the writes that apply them.
+///
+/// * s: Map of all key value pairs
+/// * c: Changed keys
+///
+/// Returns: Writes sorted by key
+///
+pub fn batch(s: &Map, c: &Changes) -> Batch {
+    let mut keys: Vec<&String> = c.keys().collect();
+    keys.sort();
+
+    keys.into_iter()
+        .map(|k| match (c[k], s.get(k)) {
+            (Change::Removed, _) | (_, None) => Op::Delete(k.clone()),
+            (_, Some(v)) => Op::Put(k.clone(), v.clone())
+        })
+        .collect()
+}
+
 /// Drivers that perform the actual reading and writing of key value
 /// pairs to another media.
 ///
@@ -31,13 +112,125 @@ pub trait Driver {
     /// * s: Map of key value pairs
     ///
     fn save(&mut self, s: &Map) -> IOCheck;
+
+    /// Pushes only the entries that changed since the last load or
+    /// save. Drivers that can write entries individually should
+    /// override this, by default all key value pairs are saved.
+    ///
+    /// * s: Map of all key value pairs
+    /// * c: Keys that changed; values of added and changed keys are
+    ///   found in `s`. Removed keys may be absent from the media.
+    ///
+    fn save_changes(&mut self, s: &Map, c: &Changes) -> IOCheck {
+        let _ = c;
+        self.save(s)
+    }
+
+    /// Sets a single key value pair on the external media. By default
+    /// all pairs are loaded, updated and saved again.
+    ///
+    /// * k: Key to set
+    /// * v: Value to set
+    ///
+    fn put(&mut self, k: &str, v: &str) -> IOCheck {
+        self.apply(&[Op::Put(k.to_string(), v.to_string())])
+    }
+
+    /// Removes a single key from the external media, doing nothing if
+    /// it is absent. By default all pairs are loaded, updated and saved
+    /// again.
+    ///
+    /// * k: Key to remove
+    ///
+    fn delete(&mut self, k: &str) -> IOCheck {
+        self.apply(&[Op::Delete(k.to_string())])
+    }
+
+    /// Applies several writes in order. By default all pairs are
+    /// loaded, updated and saved again.
+    ///
+    /// * b: Writes to apply
+    ///
+    fn apply(&mut self, b: &[Op]) -> IOCheck {
+        let mut m = self.load()?;
+        apply_to(&mut m, b);
+        self.save(&m)
+    }
+
+    /// Whether loaded key value pairs are known to be valid, for
+    /// example because they were validated when written, so the store
+    /// need not check them again.
+    ///
+    fn validated(&self) -> bool {
+        false
+    }
+
+    /// Acquires exclusive access to the external media so a read,
+    /// check and write sequence cannot interleave with other writers.
+    /// Drivers without shared media need not override this.
+    ///
+    fn lock(&mut self) -> IOCheck {
+        Ok(())
+    }
+
+    /// Releases exclusive access acquired by `lock`.
+    ///
+    fn unlock(&mut self) -> IOCheck {
+        Ok(())
+    }
 }
 
+/// Codecs that encode key value pairs as bytes and decode them again,
+/// independently of where the bytes are kept.
+///
+pub trait Format {
+
+    /// Encodes key value pairs as bytes.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Encoded bytes
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>>;
+
+    /// Decodes bytes into key value pairs.
+    ///
+    /// * b: Encoded bytes
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult;
+
+    /// Whether decoded key value pairs are known to be valid so the
+    /// store need not check them again.
+    ///
+    fn validated(&self) -> bool {
+        false
+    }
+}
+
+/// Drivers that keep all key value pairs encoded by their format as a
+/// single byte string within one file, so other drivers can transform
+/// those bytes before they are stored.
+///
+pub trait ByteDriver: Driver + Format {
+
+    /// Get the path of the file.
+    ///
+    fn path(&self) -> &Path;
+}
+
+/// Formats that ignore lines starting with '#', so other drivers may
+/// add lines of their own, such as the trailer of a `SignedDriver`.
+///
+pub trait LineComments: Format {}
+
 /// A driver that stores key value pairs within memory. Data is
 /// lost when this driver is deallocated.
 ///
 pub struct MemoryDriver {
-    data: Map
+    data: Map,
+    redact: Redact
 }
 
 /// Implements factory methods.
@@ -47,7 +240,7 @@ impl MemoryDriver {
     /// Creates a new memory driver.
     ///
     pub fn new() -> MemoryDriver {
-        MemoryDriver { data: Map::new() }
+        MemoryDriver::from(Map::new())
     }
 
     /// Creates a new memory driver initialised with some key value pairs.
@@ -55,7 +248,47 @@ impl MemoryDriver {
     /// * data: Key value pairs to initialise with
     ///
     pub fn from(data: Map) -> MemoryDriver {
-        MemoryDriver { data }
+        MemoryDriver {
+            data,
+            redact: Redact::new()
+        }
+    }
+
+    /// Sets the redaction applied when the driver is formatted.
+    ///
+    /// * r: Redaction to set
+    ///
+    pub fn with_redact(mut self, r: Redact) -> MemoryDriver {
+        self.redact = r;
+        self
+    }
+}
+
+/// Implements the default memory driver.
+///
+impl Default for MemoryDriver {
+    fn default() -> MemoryDriver {
+        MemoryDriver::new()
+    }
+}
+
+/// Implements debugging memory drivers with values redacted as
+/// configured.
+///
+impl fmt::Debug for MemoryDriver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemoryDriver {{ data: ")?;
+        self.redact.debug_map(f, &self.data)?;
+        write!(f, " }}")
+    }
+}
+
+/// Implements displaying memory drivers as 'k=v' lines, sorted by key,
+/// with values redacted as configured.
+///
+impl fmt::Display for MemoryDriver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.redact.display_map(f, &self.data)
     }
 }
 
@@ -77,4 +310,158 @@ impl Driver for MemoryDriver {
         self.data = s.clone();
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Applies only the changed entries.
+    ///
+    /// * s: Key value pairs
+    /// * c: Changed keys
+    ///
+    fn save_changes(&mut self, s: &Map, c: &Changes) -> IOCheck {
+        self.apply(&batch(s, c))
+    }
+
+    /// Sets a single pair in place.
+    ///
+    fn put(&mut self, k: &str, v: &str) -> IOCheck {
+        self.data.insert(k.to_string(), v.to_string());
+        Ok(())
+    }
+
+    /// Removes a single pair in place.
+    ///
+    fn delete(&mut self, k: &str) -> IOCheck {
+        self.data.remove(k);
+        Ok(())
+    }
+
+    /// Applies writes in place.
+    ///
+    fn apply(&mut self, b: &[Op]) -> IOCheck {
+        apply_to(&mut self.data, b);
+        Ok(())
+    }
+}
+
+/// Applies writes to a map.
+///
+/// * m: Map to update
+/// * b: Writes to apply
+///
+pub fn apply_to(m: &mut Map, b: &[Op]) {
+    for op in b {
+        match *op {
+            Op::Put(ref k, ref v) => m.insert(k.clone(), v.clone()),
+            Op::Delete(ref k) => m.remove(k)
+        };
+    }
+}
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+/// Creates an empty directory for a test to write files into.
+///
+/// * name: Unique name of the test directory
+///
+#[cfg(test)]
+pub fn test_dir(name: &str) -> ::std::path::PathBuf {
+    let dir = ::std::env::temp_dir()
+        .join(format!("rust-cookies-{}", ::std::process::id()))
+        .join(name);
+    let _ = ::std::fs::remove_dir_all(&dir);
+    ::std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// A driver that only implements the required methods.
+    struct PlainDriver {
+        data: Map,
+        saves: usize
+    }
+
+    impl Driver for PlainDriver {
+        fn load(&self) -> IOResult {
+            Ok(self.data.clone())
+        }
+
+        fn save(&mut self, s: &Map) -> IOCheck {
+            self.saves += 1;
+            self.data = s.clone();
+            Ok(())
+        }
+    }
+
+    fn ops() -> Batch {
+        vec![
+            Op::Put("a".to_string(), "1".to_string()),
+            Op::Put("b".to_string(), "2".to_string()),
+            Op::Delete("a".to_string()),
+            Op::Delete("missing".to_string())]
+    }
+
+    #[test]
+    fn default_writes_load_and_save() {
+        let mut d = PlainDriver { data: Map::new(), saves: 0 };
+
+        d.put("x", "9").unwrap();
+        d.apply(&ops()).unwrap();
+        d.delete("x").unwrap();
+
+        assert_eq!(3, d.saves);
+        assert_eq!(1, d.data.len());
+        assert_eq!("2", d.data["b"]);
+    }
+
+    #[test]
+    fn memory_writes_in_place() {
+        let mut d = MemoryDriver::new();
+
+        d.put("x", "9").unwrap();
+        d.apply(&ops()).unwrap();
+        d.delete("x").unwrap();
+
+        let m = d.load().unwrap();
+        assert_eq!(1, m.len());
+        assert_eq!("2", m["b"]);
+    }
+
+    #[test]
+    fn batch_of_changes() {
+        let mut s = Map::new();
+        s.insert("a".to_string(), "1".to_string());
+        s.insert("b".to_string(), "2".to_string());
+
+        let mut c = Changes::new();
+        c.insert("b".to_string(), Change::Changed);
+        c.insert("a".to_string(), Change::Added);
+        c.insert("z".to_string(), Change::Removed);
+
+        let expected = vec![
+            Op::Put("a".to_string(), "1".to_string()),
+            Op::Put("b".to_string(), "2".to_string()),
+            Op::Delete("z".to_string())];
+        assert_eq!(expected, batch(&s, &c));
+    }
+
+    #[test]
+    fn memory_formats_redacted() {
+        let mut m = Map::new();
+        m.insert("user".to_string(), "Rincewind".to_string());
+        m.insert("db_password".to_string(), "hunter2".to_string());
+
+        let d = MemoryDriver::from(m.clone());
+        assert_eq!("MemoryDriver { data: {\"db_password\": \"[REDACTED]\", \"user\": \"Rincewind\"} }",
+                   format!("{:?}", d));
+        assert_eq!("db_password=[REDACTED]\nuser=Rincewind\n", d.to_string());
+
+        let d = MemoryDriver::from(m).with_redact(Redact::none());
+        assert!(d.to_string().contains("hunter2"));
+    }
+}
@@ -0,0 +1,342 @@
+//!
+//! # Binary driver
+//!
+//! Reads and writes key value pairs as a compact binary snapshot that
+//! loads without parsing text. All integers are little endian:
+//!
+//! magic     4 bytes  "RCKV"
+//! version   u16      1
+//! count     u32      number of entries
+//! entries            per entry: u32 key length, key bytes,
+//!                    u32 value length, value bytes (UTF-8)
+//! checksum  u32      CRC-32 of everything before it
+//!
+//! Pairs are validated when written so the store does not validate
+//! them again when loading.
+//!
+//! `BinaryFormat` encodes snapshots wherever the bytes are kept and a
+//! `BinaryDriver` keeps them in a file.
+//!
+
+use std::io;
+use std::path::Path;
+
+use driver::{Format, IOResult, Map};
+use driver::file;
+use driver::medium::{FileMedium, FormatDriver};
+use Store;
+
+/// Identifies a binary snapshot.
+///
+pub const MAGIC: &[u8; 4] = b"RCKV";
+
+/// Version of the snapshot layout written.
+///
+pub const VERSION: u16 = 1;
+
+/// The binary snapshot format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct BinaryFormat;
+
+/// A driver that stores key value pairs within a binary snapshot file.
+///
+pub type BinaryDriver = FormatDriver<BinaryFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl BinaryFormat {
+
+    /// Creates the binary format.
+    ///
+    pub fn new() -> BinaryFormat {
+        BinaryFormat
+    }
+}
+
+/// Implements format trait for the binary format.
+///
+impl Format for BinaryFormat {
+
+    /// Reads a binary snapshot after verifying its checksum.
+    ///
+    /// * b: Snapshot bytes
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        decode(b)
+    }
+
+    /// Writes key value pairs as a binary snapshot, sorted by key.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Snapshot bytes
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        encode(s)
+    }
+
+    /// Pairs are validated by `encode` before they are written.
+    ///
+    fn validated(&self) -> bool {
+        true
+    }
+}
+
+/// Implements factory methods.
+///
+impl BinaryDriver {
+
+    /// Creates a new binary driver.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> BinaryDriver {
+        FormatDriver::with(BinaryFormat::new(), FileMedium::new(path))
+    }
+}
+
+/// Writes key value pairs as a binary snapshot, sorted by key.
+///
+/// * s: Key value pairs
+///
+/// Returns: Snapshot bytes
+///
+pub fn encode(s: &Map) -> io::Result<Vec<u8>> {
+    let mut keys: Vec<&String> = s.keys().collect();
+    keys.sort();
+
+    let mut b = Vec::new();
+    b.extend_from_slice(MAGIC);
+    b.extend_from_slice(&VERSION.to_le_bytes());
+    b.extend_from_slice(&len32(keys.len())?.to_le_bytes());
+
+    for k in keys {
+        let v = &s[k];
+
+        if !Store::check_key(k) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid key '{}'", k)));
+        }
+
+        if !Store::check_value(v) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid value for '{}'", k)));
+        }
+
+        b.extend_from_slice(&len32(k.len())?.to_le_bytes());
+        b.extend_from_slice(k.as_bytes());
+        b.extend_from_slice(&len32(v.len())?.to_le_bytes());
+        b.extend_from_slice(v.as_bytes());
+    }
+
+    let sum = crc32(&b);
+    b.extend_from_slice(&sum.to_le_bytes());
+    Ok(b)
+}
+
+/// Reads a binary snapshot after verifying its checksum.
+///
+/// * b: Snapshot bytes
+///
+/// Returns: Map of key value pairs
+///
+pub fn decode(b: &[u8]) -> IOResult {
+    if b.len() < 14 || &b[..4] != MAGIC {
+        return Err(invalid("Not a binary snapshot"));
+    }
+
+    let (body, sum) = b.split_at(b.len() - 4);
+    if crc32(body) != u32::from_le_bytes([sum[0], sum[1], sum[2], sum[3]]) {
+        return Err(invalid("Binary snapshot checksum mismatch"));
+    }
+
+    let mut r = Reader { b: body, at: 4 };
+
+    let v = r.take(2)?;
+    let version = u16::from_le_bytes([v[0], v[1]]);
+    if version != VERSION {
+        return Err(invalid(&format!("Unsupported binary snapshot version {}", version)));
+    }
+
+    let count = r.u32()? as usize;
+    let mut m = Map::with_capacity(count.min(body.len() / 8));
+
+    for _ in 0..count {
+        let k = r.string()?;
+        let v = r.string()?;
+        m.insert(k, v);
+    }
+
+    if r.at != body.len() {
+        return Err(invalid("Unexpected bytes after binary snapshot entries"));
+    }
+
+    Ok(m)
+}
+
+/// Converts the store's text format into a binary snapshot.
+///
+/// * text: Text in the store's own format
+///
+/// Returns: Snapshot bytes
+///
+pub fn from_text(text: &str) -> io::Result<Vec<u8>> {
+    encode(&file::parse(text)?)
+}
+
+/// Converts a binary snapshot into the store's text format.
+///
+/// * b: Snapshot bytes
+///
+/// Returns: Text in the store's own format
+///
+pub fn to_text(b: &[u8]) -> io::Result<String> {
+    Ok(file::format(&decode(b)?))
+}
+
+/// Reads fields from snapshot bytes.
+///
+struct Reader<'a> {
+    b: &'a [u8],
+    at: usize
+}
+
+/// Implements reading snapshot fields.
+///
+impl<'a> Reader<'a> {
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.b.len() - self.at < n {
+            return Err(invalid("Binary snapshot is truncated"));
+        }
+        let s = &self.b[self.at..self.at + n];
+        self.at += n;
+        Ok(s)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let s = self.take(4)?;
+        Ok(u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let n = self.u32()? as usize;
+        let s = self.take(n)?;
+        String::from_utf8(s.to_vec()).map_err(|_| invalid("Binary snapshot holds invalid UTF-8"))
+    }
+}
+
+/// Converts a length to the u32 the layout stores.
+///
+fn len32(n: usize) -> io::Result<u32> {
+    if n > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Too large for a binary snapshot"));
+    }
+    Ok(n as u32)
+}
+
+/// Computes the CRC-32 (IEEE) checksum of some bytes.
+///
+pub fn crc32(b: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, t) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *t = c;
+    }
+
+    let mut crc = !0u32;
+    for &x in b {
+        crc = table[((crc ^ u32::from(x)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use driver::{Driver, Map};
+    use driver::test_dir;
+    use super::*;
+    use Store;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        m.insert("a".to_string(), "Rincewind".to_string());
+        m.insert("b".to_string(), "caf\u{e9} = #!".to_string());
+        m.insert("c".to_string(), "".to_string());
+        m
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("binary_save").join("store.bin");
+
+        let mut d = BinaryDriver::new(&path);
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(d));
+        s.load().unwrap();
+        assert_eq!(Some(&"Rincewind".to_string()), s.get("a"));
+    }
+
+    #[test]
+    fn save_validates() {
+        let mut m = make_map();
+        m.insert("1a".to_string(), "x".to_string());
+        assert!(encode(&m).is_err());
+
+        let mut m = make_map();
+        m.insert("a".to_string(), "x\ny".to_string());
+        assert!(encode(&m).is_err());
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let b = encode(&make_map()).unwrap();
+
+        let mut flipped = b.clone();
+        flipped[12] ^= 1;
+        let e = decode(&flipped).unwrap_err();
+        assert!(e.to_string().contains("checksum"), "{}", e);
+
+        assert!(decode(&b[..b.len() - 1]).is_err());
+        assert!(decode(b"a=1\n").is_err());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let text = file::format(&make_map());
+        let b = from_text(&text).unwrap();
+        assert_eq!(text, to_text(&b).unwrap());
+        assert_eq!(b, from_text(&to_text(&b).unwrap()).unwrap());
+    }
+}
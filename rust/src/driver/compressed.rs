@@ -0,0 +1,309 @@
+//!
+//! # Compressed driver
+//!
+//! Wraps a format driver so the bytes it keeps within its medium are
+//! compressed on save and decompressed on load. Algorithms are enabled
+//! by cargo features:
+//!
+//! gzip   Gzip via 'flate2'
+//! zstd   Zstandard via 'zstd'
+//!
+//! The format of a loaded file is sniffed from its leading magic bytes
+//! so compressed and uncompressed files are read interchangeably.
+//!
+
+use std::io;
+use std::io::Read;
+
+use driver::{Format, IOResult, Map};
+use driver::medium::{FormatDriver, Medium};
+
+/// Leading bytes of a gzip stream.
+///
+pub const GZIP_MAGIC: &[u8; 2] = b"\x1f\x8b";
+
+/// Leading bytes of a Zstandard frame.
+///
+pub const ZSTD_MAGIC: &[u8; 4] = b"\x28\xb5\x2f\xfd";
+
+/// Compression algorithms that may be written.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd
+}
+
+/// Implements the default compression, gzip when enabled.
+///
+impl Default for Compression {
+    fn default() -> Compression {
+        #[cfg(feature = "gzip")]
+        return Compression::Gzip;
+
+        #[cfg(not(feature = "gzip"))]
+        return Compression::Zstd;
+    }
+}
+
+/// A format that compresses the bytes of another format.
+///
+pub struct CompressedFormat<F: Format> {
+    inner: F,
+    compression: Compression
+}
+
+/// A driver that compresses the bytes another format driver keeps
+/// within its medium.
+///
+pub type CompressedDriver<F, M> = FormatDriver<CompressedFormat<F>, M>;
+
+/// Implements factory methods.
+///
+impl<F: Format> CompressedFormat<F> {
+
+    /// Creates a new compressed format writing the default
+    /// compression.
+    ///
+    /// * inner: Format whose bytes are compressed
+    ///
+    pub fn new(inner: F) -> CompressedFormat<F> {
+        CompressedFormat {
+            inner,
+            compression: Compression::default()
+        }
+    }
+
+    /// Sets the compression written on encode. Decoding accepts any
+    /// enabled compression regardless.
+    ///
+    /// * c: Compression to write
+    ///
+    pub fn compression(mut self, c: Compression) -> CompressedFormat<F> {
+        self.compression = c;
+        self
+    }
+
+    /// Get the wrapped format.
+    ///
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+}
+
+/// Implements format trait for the compressed format.
+///
+impl<F: Format> Format for CompressedFormat<F> {
+
+    /// Encodes key value pairs with the wrapped format and compresses
+    /// the bytes.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Compressed bytes
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        compress(&self.inner.encode(s)?, self.compression)
+    }
+
+    /// Decompresses bytes and decodes them with the wrapped format.
+    ///
+    /// * b: Bytes that may be compressed
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        self.inner.decode(&decompress(b)?)
+    }
+
+    fn validated(&self) -> bool {
+        self.inner.validated()
+    }
+}
+
+/// Implements factory methods.
+///
+impl<F: Format, M: Medium> CompressedDriver<F, M> {
+
+    /// Creates a new compressed driver writing the default compression
+    /// to the medium of another driver.
+    ///
+    /// * inner: Driver whose bytes are compressed
+    ///
+    pub fn new(inner: FormatDriver<F, M>) -> CompressedDriver<F, M> {
+        let (format, medium) = inner.into_parts();
+        FormatDriver::with(CompressedFormat::new(format), medium)
+    }
+
+    /// Sets the compression written on save. Loading accepts any
+    /// enabled compression regardless.
+    ///
+    /// * c: Compression to write
+    ///
+    pub fn compression(mut self, c: Compression) -> CompressedDriver<F, M> {
+        self.format_mut().compression = c;
+        self
+    }
+}
+
+/// Compresses bytes.
+///
+/// * b: Bytes to compress
+/// * c: Compression to use
+///
+/// Returns: Compressed bytes
+///
+pub fn compress(b: &[u8], c: Compression) -> io::Result<Vec<u8>> {
+    match c {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            use std::io::Write;
+
+            let mut e = ::flate2::write::GzEncoder::new(
+                Vec::new(), ::flate2::Compression::default());
+            e.write_all(b)?;
+            e.finish()
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => ::zstd::encode_all(b, 0)
+    }
+}
+
+/// Decompresses bytes, sniffing the compression used. Bytes that are
+/// not compressed are returned as they are.
+///
+/// * b: Bytes that may be compressed
+///
+/// Returns: Decompressed bytes
+///
+pub fn decompress(b: &[u8]) -> io::Result<Vec<u8>> {
+    if b.starts_with(GZIP_MAGIC) {
+        return gunzip(b);
+    }
+
+    if b.starts_with(ZSTD_MAGIC) {
+        return unzstd(b);
+    }
+
+    Ok(b.to_vec())
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(b: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    ::flate2::read::GzDecoder::new(b).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "zstd")]
+fn unzstd(b: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    ::zstd::stream::read::Decoder::new(b)?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unzstd(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unsupported("zstd"))
+}
+
+/// Creates an error for a compression whose feature is disabled.
+///
+#[allow(dead_code)]
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("File is {} compressed but the '{}' feature is not enabled", feature, feature))
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, FileDriver, FormatDriver, JsonDriver, JsonFormat, Map, MemoryMedium};
+    use driver::test_dir;
+    use super::*;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        for i in 0..100 {
+            m.insert(format!("key_{}", i), "a value that repeats".to_string());
+        }
+        m
+    }
+
+    fn round_trip(c: Compression, magic: &[u8]) {
+        let path = test_dir(&format!("compressed_{:?}", c)).join("store.json");
+
+        let mut d = CompressedDriver::new(JsonDriver::new(&path)).compression(c);
+        d.save(&make_map()).unwrap();
+
+        let b = fs::read(&path).unwrap();
+        assert!(b.starts_with(magic));
+        assert!(b.len() < 500, "{} bytes", b.len());
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        round_trip(Compression::Gzip, GZIP_MAGIC);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Compression::Zstd, ZSTD_MAGIC);
+    }
+
+    #[test]
+    fn reads_uncompressed_and_missing_files() {
+        let path = test_dir("compressed_plain").join("store.txt");
+
+        let d = CompressedDriver::new(FileDriver::new(&path));
+        assert!(d.load().unwrap().is_empty());
+
+        FileDriver::new(&path).save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[test]
+    fn compresses_any_medium() {
+        let mut d = CompressedDriver::new(FormatDriver::with(JsonFormat::new(), MemoryMedium::new()));
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_file_settings() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = test_dir("compressed_mode").join("store.txt");
+        let mut d = CompressedDriver::new(FileDriver::new(&path).mode(0o640));
+        d.save(&make_map()).unwrap();
+        assert_eq!(0o640, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn corrupt_data_fails() {
+        let mut b = compress(b"a=1\n", Compression::default()).unwrap();
+        let n = b.len();
+        b.truncate(n / 2);
+        assert!(decompress(&b).is_err());
+    }
+}
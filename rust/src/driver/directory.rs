@@ -0,0 +1,336 @@
+//!
+//! # Directory driver
+//!
+//! Reads and writes key value pairs as a directory holding one file
+//! per key, the file name being the key and its content the value.
+//! This is how Kubernetes ConfigMaps and Docker secrets are mounted.
+//!
+//! Hidden entries, such as the '..data' links Kubernetes adds, and
+//! sub directories are ignored. New files are readable and writable by
+//! their owner only.
+//!
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use driver::{Changes, Driver, IOCheck, IOResult, Map, Op};
+use driver::batch;
+use driver::file::{write_atomic_via, LockFile};
+use Store;
+
+/// A driver that stores each key value pair in its own file.
+///
+pub struct DirectoryDriver {
+    path: PathBuf,
+    lock: LockFile
+}
+
+/// Implements factory methods.
+///
+impl DirectoryDriver {
+
+    /// Creates a new directory driver.
+    ///
+    /// * path: Path of the directory to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> DirectoryDriver {
+        DirectoryDriver {
+            path: path.as_ref().to_path_buf(),
+            lock: LockFile::new(path.as_ref())
+        }
+    }
+
+    /// Sets how long `lock` waits for another writer to release the
+    /// directory before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn lock_timeout(mut self, t: Duration) -> DirectoryDriver {
+        self.lock = self.lock.timeout(t);
+        self
+    }
+
+    /// Get the path of the directory.
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Lists the keys of the files within the directory.
+    ///
+    fn keys(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(e) => e,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+        };
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+
+            let name = match name.to_str() {
+                Some(n) => n.to_string(),
+                None => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid key file name '{}'", name.to_string_lossy())))
+            };
+
+            if name.starts_with('.') || fs::metadata(entry.path())?.is_dir() {
+                continue;
+            }
+
+            if !Store::check_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid key file name '{}'", name)));
+            }
+
+            keys.push(name);
+        }
+
+        Ok(keys)
+    }
+
+    /// Writes the file of a key atomically via a hidden temporary file.
+    ///
+    fn write(&self, k: &str, v: &str) -> IOCheck {
+        let path = self.file(k)?;
+        let tmp = self.path.join(format!(".{}.tmp", k));
+        write_atomic_via(&path, &tmp, v.as_bytes(), 0o600)
+    }
+
+    /// Removes the file of a key if it exists.
+    ///
+    fn remove(&self, k: &str) -> IOCheck {
+        match fs::remove_file(self.file(k)?) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r
+        }
+    }
+
+    /// Gets the path of the file of a key, refusing keys that could
+    /// name a file outside the directory.
+    ///
+    fn file(&self, k: &str) -> io::Result<PathBuf> {
+        if !Store::check_key(k) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid key '{}'", k)));
+        }
+        Ok(self.path.join(k))
+    }
+}
+
+/// Implements driver trait for the directory driver.
+///
+impl Driver for DirectoryDriver {
+
+    /// Reads every key file, trimming one trailing newline from each
+    /// value. A missing directory is treated as an empty store.
+    ///
+    fn load(&self) -> IOResult {
+        let mut m = Map::new();
+
+        for k in self.keys()? {
+            let mut v = fs::read_to_string(self.path.join(&k))?;
+
+            if v.ends_with('\n') {
+                v.pop();
+                if v.ends_with('\r') {
+                    v.pop();
+                }
+            }
+
+            m.insert(k, v);
+        }
+
+        Ok(m)
+    }
+
+    /// Writes a file per key and removes files of keys no longer in
+    /// the store.
+    ///
+    /// * s: Key value pairs
+    ///
+    fn save(&mut self, s: &Map) -> IOCheck {
+        fs::create_dir_all(&self.path)?;
+
+        for (k, v) in s.iter() {
+            self.write(k, v)?;
+        }
+
+        for k in self.keys()? {
+            if !s.contains_key(&k) {
+                self.remove(&k)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes or removes only the files of changed keys.
+    ///
+    /// * s: Key value pairs
+    /// * c: Changed keys
+    ///
+    fn save_changes(&mut self, s: &Map, c: &Changes) -> IOCheck {
+        self.apply(&batch(s, c))
+    }
+
+    /// Writes the file of a single key.
+    ///
+    fn put(&mut self, k: &str, v: &str) -> IOCheck {
+        fs::create_dir_all(&self.path)?;
+        self.write(k, v)
+    }
+
+    /// Removes the file of a single key.
+    ///
+    fn delete(&mut self, k: &str) -> IOCheck {
+        self.remove(k)
+    }
+
+    /// Writes or removes the file of each key in turn.
+    ///
+    fn apply(&mut self, b: &[Op]) -> IOCheck {
+        fs::create_dir_all(&self.path)?;
+
+        for op in b {
+            match *op {
+                Op::Put(ref k, ref v) => self.write(k, v)?,
+                Op::Delete(ref k) => self.remove(k)?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the lock file, waiting for any other holder to remove
+    /// it first.
+    ///
+    fn lock(&mut self) -> IOCheck {
+        self.lock.acquire()
+    }
+
+    /// Removes the lock file.
+    ///
+    fn unlock(&mut self) -> IOCheck {
+        self.lock.release()
+    }
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::io;
+
+    use driver::{Driver, Map, Op};
+    use driver::test_dir;
+    use super::DirectoryDriver;
+
+    #[test]
+    fn load_mounted_tree() {
+        let dir = test_dir("dir_load");
+        fs::write(dir.join("host"), "db.local\n").unwrap();
+        fs::write(dir.join("password"), "hunter2").unwrap();
+        fs::write(dir.join("crlf"), "abc\r\n").unwrap();
+        fs::write(dir.join("twice"), "abc\n\n").unwrap();
+        fs::create_dir(dir.join("..data")).unwrap();
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("..data").join("host"), "ignored").unwrap();
+
+        let m = DirectoryDriver::new(&dir).load().unwrap();
+        assert_eq!(4, m.len());
+        assert_eq!("db.local", m["host"]);
+        assert_eq!("hunter2", m["password"]);
+        assert_eq!("abc", m["crlf"]);
+        assert_eq!("abc\n", m["twice"]);
+    }
+
+    #[test]
+    fn load_rejects_bad_file_name() {
+        let dir = test_dir("dir_bad_name");
+        fs::write(dir.join("db-host"), "x").unwrap();
+
+        let e = DirectoryDriver::new(&dir).load().unwrap_err();
+        assert!(e.to_string().contains("db-host"), "{}", e);
+    }
+
+    #[test]
+    fn save_removes_stale_keys() {
+        let dir = test_dir("dir_save").join("conf");
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), "1".to_string());
+        m.insert("b".to_string(), "2".to_string());
+
+        let mut d = DirectoryDriver::new(&dir);
+        d.save(&m).unwrap();
+
+        m.remove("a");
+        m.insert("c".to_string(), "3".to_string());
+        d.save(&m).unwrap();
+
+        assert!(!dir.join("a").exists());
+        assert_eq!("3", fs::read_to_string(dir.join("c")).unwrap());
+        assert_eq!(m, d.load().unwrap());
+    }
+
+    #[test]
+    fn apply_touches_single_files() {
+        let dir = test_dir("dir_apply");
+        fs::write(dir.join("a"), "1").unwrap();
+        fs::write(dir.join("b"), "2").unwrap();
+
+        let mut d = DirectoryDriver::new(&dir);
+        d.apply(&[
+            Op::Put("c".to_string(), "3".to_string()),
+            Op::Delete("a".to_string()),
+            Op::Delete("missing".to_string())]).unwrap();
+
+        let m = d.load().unwrap();
+        assert_eq!(2, m.len());
+        assert_eq!("2", m["b"]);
+        assert_eq!("3", m["c"]);
+    }
+
+    #[test]
+    fn rejects_keys_outside_directory() {
+        let root = test_dir("dir_escape");
+        let dir = root.join("conf");
+        fs::write(root.join("x"), "keep").unwrap();
+
+        let mut d = DirectoryDriver::new(&dir);
+        for k in &["../x", "/tmp/x", "a/b", ""] {
+            let e = d.put(k, "1").unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, e.kind(), "{}", k);
+        }
+
+        let e = d.apply(&[Op::Delete("../x".to_string())]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+        assert_eq!("keep", fs::read_to_string(root.join("x")).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("dir_private");
+        DirectoryDriver::new(&dir).put("a", "1").unwrap();
+
+        let mode = fs::metadata(dir.join("a")).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+}
@@ -0,0 +1,254 @@
+//!
+//! # Dotenv driver
+//!
+//! Reads and writes key value pairs as a '.env' file in the style
+//! docker-compose reads:
+//!
+//! # comment
+//! export NAME=value   # inline comment
+//! SINGLE='taken $literally'
+//! DOUBLE="tab\tand \"quotes\""
+//!
+//! The 'export ' prefix is optional. Unquoted values end at a '#'
+//! preceded by whitespace and are trimmed. Single quoted values are
+//! literal, double quoted values support '\n', '\r', '\t', '\"', '\\'
+//! and '\$' escapes. Variables are not interpolated.
+//!
+//! `DotenvFormat` encodes dotenv text wherever the bytes are kept and a
+//! `DotenvDriver` keeps them in a file.
+//!
+
+use std::io;
+use std::path::Path;
+
+use driver::{Format, IOResult, LineComments, Map};
+use driver::medium::{FileMedium, FormatDriver};
+use driver::quote::DOTENV;
+use Store;
+
+/// The dotenv format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct DotenvFormat;
+
+/// A driver that stores key value pairs within a '.env' file.
+///
+pub type DotenvDriver = FormatDriver<DotenvFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl DotenvFormat {
+
+    /// Creates the dotenv format.
+    ///
+    pub fn new() -> DotenvFormat {
+        DotenvFormat
+    }
+}
+
+/// Implements format trait for the dotenv format.
+///
+impl Format for DotenvFormat {
+
+    /// Parses dotenv text into key value pairs.
+    ///
+    /// * b: Dotenv text
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let text = String::from_utf8(b.to_vec()).map_err(|e| invalid(e.to_string()))?;
+        let mut m = Map::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let n = i + 1;
+            let mut line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("export") {
+                if rest.starts_with(char::is_whitespace) {
+                    line = rest.trim_start();
+                }
+            }
+
+            let eq = line.find('=').ok_or_else(|| invalid(format!(
+                "Invalid key value pair at line {}", n)))?;
+
+            let k = line[..eq].trim();
+            if !Store::check_key(k) {
+                return Err(invalid(format!("Invalid key '{}' at line {}", k, n)));
+            }
+
+            let v = DOTENV.unquote(&line[eq + 1..]).map_err(|e| invalid(format!(
+                "{} at line {}", e, n)))?;
+
+            if !Store::check_value(&v) {
+                return Err(invalid(format!("Invalid value for '{}' at line {}", k, n)));
+            }
+
+            m.insert(k.to_string(), v);
+        }
+
+        Ok(m)
+    }
+
+    /// Formats key value pairs as dotenv text, sorted by key. Values
+    /// are quoted only when needed.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Dotenv text
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        let mut keys: Vec<&String> = s.keys().collect();
+        keys.sort();
+
+        let mut out = String::new();
+        for k in keys {
+            out.push_str(&format!("{}={}\n", k, quote(&s[k])));
+        }
+
+        Ok(out.into_bytes())
+    }
+}
+
+/// Implements line comments for the dotenv format.
+///
+impl LineComments for DotenvFormat {}
+
+/// Implements factory methods.
+///
+impl DotenvDriver {
+
+    /// Creates a new dotenv driver.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> DotenvDriver {
+        FormatDriver::with(DotenvFormat::new(), FileMedium::new(path))
+    }
+}
+
+/// Quotes a value if it contains anything a reader might interpret.
+/// Single quotes are preferred as their content is literal.
+///
+fn quote(v: &str) -> String {
+    let plain = v.chars().all(|c| c.is_alphanumeric() || "_-.,/:@+=%".contains(c));
+    if plain {
+        return v.to_string();
+    }
+
+    if !v.contains('\'') {
+        return format!("'{}'", v);
+    }
+
+    let mut out = String::from("\"");
+    for c in v.chars() {
+        match c {
+            '"' | '\\' | '$' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Format, Map};
+    use driver::test_dir;
+    use super::{DotenvDriver, DotenvFormat};
+    use Store;
+
+    fn decode(s: &str) -> ::driver::IOResult {
+        DotenvFormat::new().decode(s.as_bytes())
+    }
+
+    #[test]
+    fn syntax() {
+        let m = decode(r#"
+# Project settings
+export NAME=app
+PORT = 8080   # inline comment
+exporter=1
+URL=http://x/#frag
+SINGLE='$HOME \t # not a comment'
+DOUBLE="a \"b\" \t \$HOME \\ \q" # comment
+EMPTY=
+EMPTY_QUOTED=""
+        "#).unwrap();
+
+        assert_eq!(8, m.len());
+        assert_eq!("app", m["NAME"]);
+        assert_eq!("8080", m["PORT"]);
+        assert_eq!("1", m["exporter"]);
+        assert_eq!("http://x/#frag", m["URL"]);
+        assert_eq!("$HOME \\t # not a comment", m["SINGLE"]);
+        assert_eq!("a \"b\" \t $HOME \\ \\q", m["DOUBLE"]);
+        assert_eq!("", m["EMPTY"]);
+        assert_eq!("", m["EMPTY_QUOTED"]);
+    }
+
+    #[test]
+    fn invalid_lines_fail() {
+        for env in &["NAME", "BAD-KEY=1", "A=\"open", "A='open", "A=\"x\" y",
+                     "A=\"multi\\nline\""] {
+            assert!(decode(env).is_err(), "{} should fail", env);
+        }
+    }
+
+    #[test]
+    fn store_loads_env() {
+        let path = test_dir("dotenv_store").join(".env");
+        fs::write(&path, "export DEBUG=true\nWORKERS=4\n").unwrap();
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(DotenvDriver::new(&path)));
+        s.load().unwrap();
+
+        assert!(s.get_bool("DEBUG").unwrap().unwrap());
+        assert_eq!(4, s.get_u8("WORKERS").unwrap().unwrap());
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("dotenv_save").join(".env");
+
+        let mut m = Map::new();
+        m.insert("PLAIN".to_string(), "a-b.c".to_string());
+        m.insert("SPACES".to_string(), " x # y ".to_string());
+        m.insert("BOTH".to_string(), "it's \"$x\"\t\\".to_string());
+        m.insert("EMPTY".to_string(), "".to_string());
+
+        let mut d = DotenvDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("PLAIN=a-b.c\n"), "{}", text);
+        assert!(text.contains("SPACES=' x # y '\n"), "{}", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+}
@@ -0,0 +1,589 @@
+//!
+//! # Encrypted driver
+//!
+//! Wraps a format driver so the bytes it keeps within its medium are
+//! encrypted and authenticated at rest using XChaCha20-Poly1305.
+//! Enabled by the 'encryption' cargo feature. The bytes hold:
+//!
+//! magic      4 bytes   "RCKE"
+//! version    u8        1
+//! nonce      24 bytes  random per save
+//! ciphertext           encrypted bytes of the wrapped driver's format
+//!                      followed by a 16 byte tag
+//!
+//! Keys are 32 bytes, written as 64 hex digits within key files and
+//! environment variables. Tampering or the wrong key is reported as an
+//! `EncryptionError` so it can be told apart from malformed data.
+//!
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+
+use driver::{Driver, Format, IOCheck, IOResult, Map};
+use driver::medium::{FormatDriver, Medium};
+
+/// Identifies an encrypted file.
+///
+pub const MAGIC: &[u8; 4] = b"RCKE";
+
+/// Version of the encrypted layout written.
+///
+pub const VERSION: u8 = 1;
+
+/// Starts a value sealed by `seal`.
+///
+pub const SEALED_PREFIX: &str = "ENC[v1,";
+
+const NONCE_LEN: usize = 24;
+
+const TAG_LEN: usize = 16;
+
+/// Errors specific to encryption, carried within an `io::Error` of
+/// kind `Other`. Use `EncryptionError::of` to find one.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptionError {
+
+    /// The data was tampered with or none of the keys match it.
+    Unauthenticated,
+
+    /// A key could not be obtained or is malformed.
+    Key(String),
+
+    /// The value of a key is sealed and no key is configured to
+    /// decrypt it.
+    Sealed(String)
+}
+
+/// Implements encryption error methods.
+///
+impl EncryptionError {
+
+    /// Finds the encryption error within an IO error.
+    ///
+    /// * e: IO error returned by the crate
+    ///
+    /// Returns: Encryption error within an option
+    ///
+    pub fn of(e: &io::Error) -> Option<&EncryptionError> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<EncryptionError>())
+    }
+}
+
+/// Implements displaying encryption errors.
+///
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncryptionError::Unauthenticated =>
+                write!(f, "Decryption failed: data was tampered with or the key is wrong"),
+            EncryptionError::Key(ref msg) => write!(f, "Invalid encryption key: {}", msg),
+            EncryptionError::Sealed(ref k) =>
+                write!(f, "Value of '{}' is sealed and no key is configured", k)
+        }
+    }
+}
+
+impl error::Error for EncryptionError {}
+
+/// Implements wrapping encryption errors as IO errors.
+///
+impl From<EncryptionError> for io::Error {
+    fn from(e: EncryptionError) -> io::Error {
+        io::Error::other(e)
+    }
+}
+
+/// A 32 byte encryption key.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; 32]);
+
+/// Implements key factory methods.
+///
+impl Key {
+
+    /// Creates a key from raw bytes.
+    ///
+    /// * b: Key bytes
+    ///
+    pub fn new(b: [u8; 32]) -> Key {
+        Key(b)
+    }
+
+    /// Creates a new random key.
+    ///
+    pub fn generate() -> Key {
+        let k = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut b = [0u8; 32];
+        b.copy_from_slice(&k);
+        Key(b)
+    }
+
+    /// Parses a key from 64 hex digits, ignoring surrounding
+    /// whitespace.
+    ///
+    /// * s: Hex digits
+    ///
+    pub fn from_hex(s: &str) -> io::Result<Key> {
+        let s = s.trim();
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(EncryptionError::Key("Expected 64 hex digits".to_string()).into());
+        }
+
+        let mut b = [0u8; 32];
+        for (i, x) in b.iter_mut().enumerate() {
+            *x = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| {
+                EncryptionError::Key("Expected 64 hex digits".to_string())
+            })?;
+        }
+
+        Ok(Key(b))
+    }
+
+    /// Reads a key written as hex digits within a file.
+    ///
+    /// * path: Path of the key file
+    ///
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Key> {
+        let text = fs::read_to_string(path.as_ref()).map_err(|e| EncryptionError::Key(
+            format!("Cannot read '{}': {}", path.as_ref().display(), e)))?;
+        Key::from_hex(&text)
+    }
+
+    /// Reads a key written as hex digits within an environment
+    /// variable.
+    ///
+    /// * var: Name of the variable
+    ///
+    pub fn from_env(var: &str) -> io::Result<Key> {
+        let text = env::var(var).map_err(|e| EncryptionError::Key(
+            format!("Cannot read '{}': {}", var, e)))?;
+        Key::from_hex(&text)
+    }
+
+    /// Writes the key as hex digits.
+    ///
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Implements debugging keys without revealing them.
+///
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+/// Supplies the current key each time one is needed.
+///
+pub type KeyProvider = Box<dyn Fn() -> io::Result<Key>>;
+
+/// A format that encrypts the bytes of another format.
+///
+pub struct EncryptedFormat<F: Format> {
+    inner: F,
+    key: KeyProvider,
+    previous: Vec<Key>
+}
+
+/// A driver that encrypts the bytes another format driver keeps within
+/// its medium.
+///
+pub type EncryptedDriver<F, M> = FormatDriver<EncryptedFormat<F>, M>;
+
+/// Implements factory methods.
+///
+impl<F: Format> EncryptedFormat<F> {
+
+    /// Creates a new encrypted format that asks for the key each time
+    /// bytes are encrypted or decrypted, so keys need not be held.
+    ///
+    /// * inner: Format whose bytes are encrypted
+    /// * key: Supplies the key to encrypt and decrypt with
+    ///
+    pub fn new<K>(inner: F, key: K) -> EncryptedFormat<F>
+        where K: Fn() -> io::Result<Key> + 'static {
+
+        EncryptedFormat {
+            inner,
+            key: Box::new(key),
+            previous: Vec::new()
+        }
+    }
+
+    /// Adds a key the bytes may have been encrypted with before the
+    /// current one. Decoding tries it when the current key fails and
+    /// encoding always uses the current key.
+    ///
+    /// * k: Previous key
+    ///
+    pub fn previous_key(mut self, k: Key) -> EncryptedFormat<F> {
+        self.previous.push(k);
+        self
+    }
+
+    /// Get the wrapped format.
+    ///
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+}
+
+/// Implements format trait for the encrypted format.
+///
+impl<F: Format> Format for EncryptedFormat<F> {
+
+    /// Encodes key value pairs with the wrapped format and encrypts
+    /// the bytes with the current key.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Encrypted layout
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        Ok(encrypt(&(self.key)()?, &self.inner.encode(s)?))
+    }
+
+    /// Decrypts bytes with the current or a previous key and decodes
+    /// them with the wrapped format.
+    ///
+    /// * b: Encrypted layout
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let mut keys = vec![(self.key)()?];
+        keys.extend(self.previous.iter().cloned());
+        self.inner.decode(&decrypt(&keys, b)?)
+    }
+
+    fn validated(&self) -> bool {
+        self.inner.validated()
+    }
+}
+
+/// Implements factory methods.
+///
+impl<F: Format, M: Medium> EncryptedDriver<F, M> {
+
+    /// Creates a new encrypted driver with a fixed key, writing to the
+    /// medium of another driver.
+    ///
+    /// * inner: Driver whose bytes are encrypted
+    /// * key: Key to encrypt and decrypt with
+    ///
+    pub fn new(inner: FormatDriver<F, M>, key: Key) -> EncryptedDriver<F, M> {
+        EncryptedDriver::with_provider(inner, move || Ok(key.clone()))
+    }
+
+    /// Creates a new encrypted driver that asks for the key each time
+    /// the medium is read or written, so keys need not be held.
+    ///
+    /// * inner: Driver whose bytes are encrypted
+    /// * key: Supplies the key to encrypt and decrypt with
+    ///
+    pub fn with_provider<K>(inner: FormatDriver<F, M>, key: K) -> EncryptedDriver<F, M>
+        where K: Fn() -> io::Result<Key> + 'static {
+
+        let (format, medium) = inner.into_parts();
+        FormatDriver::with(EncryptedFormat::new(format, key), medium)
+    }
+
+    /// Adds a key the bytes may have been encrypted with before the
+    /// current one. Loading tries it when the current key fails and
+    /// the next save encrypts with the current key.
+    ///
+    /// * k: Previous key
+    ///
+    pub fn previous_key(mut self, k: Key) -> EncryptedDriver<F, M> {
+        self.format_mut().previous.push(k);
+        self
+    }
+
+    /// Re-encrypts the stored bytes with the current key. Once done
+    /// previous keys are no longer needed.
+    ///
+    pub fn rotate(&mut self) -> IOCheck {
+        self.lock()?;
+        let r = self.load().and_then(|m| self.save(&m));
+        self.unlock()?;
+        r
+    }
+}
+
+/// Encrypts bytes with a fresh random nonce.
+///
+/// * k: Key to encrypt with
+/// * b: Bytes to encrypt
+///
+/// Returns: Encrypted layout
+///
+pub fn encrypt(k: &Key, b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(5 + NONCE_LEN + b.len() + TAG_LEN);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = XChaCha20Poly1305::new(&k.0.into())
+        .encrypt(&nonce, Payload { msg: b, aad: &out })
+        .expect("Encrypting within memory cannot fail");
+
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    out
+}
+
+/// Decrypts bytes with the first key that authenticates them.
+///
+/// * keys: Keys to try in order
+/// * b: Encrypted layout
+///
+/// Returns: Decrypted bytes
+///
+pub fn decrypt(keys: &[Key], b: &[u8]) -> io::Result<Vec<u8>> {
+    if b.len() < 5 + NONCE_LEN || &b[..4] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an encrypted file"));
+    }
+
+    if b[4] != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported encrypted file version {}", b[4])));
+    }
+
+    let (header, rest) = b.split_at(5);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+
+    keys.iter()
+        .filter_map(|k| XChaCha20Poly1305::new(&k.0.into())
+            .decrypt(nonce.into(), Payload { msg: sealed, aad: header })
+            .ok())
+        .next()
+        .ok_or_else(|| EncryptionError::Unauthenticated.into())
+}
+
+/// Checks whether a value was sealed by `seal`: it starts with
+/// `SEALED_PREFIX` and holds base64 long enough for a nonce and tag.
+///
+/// * v: Value to check
+///
+pub fn is_sealed(v: &str) -> bool {
+    sealed_bytes(v).is_some()
+}
+
+/// Encrypts a single value so it can be kept within a plaintext file
+/// as 'ENC[v1,...]' where '...' is the base64 nonce and ciphertext.
+/// The key of the value is authenticated too so sealed values cannot
+/// be moved between keys.
+///
+/// * key: Key to encrypt with
+/// * k: Key of the value
+/// * v: Value to seal
+///
+/// Returns: Sealed value
+///
+pub fn seal(key: &Key, k: &str, v: &str) -> String {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = XChaCha20Poly1305::new(&key.0.into())
+        .encrypt(&nonce, Payload { msg: v.as_bytes(), aad: k.as_bytes() })
+        .expect("Encrypting within memory cannot fail");
+
+    let mut b = nonce.to_vec();
+    b.extend_from_slice(&sealed);
+    format!("{}{}]", SEALED_PREFIX, BASE64.encode(&b))
+}
+
+/// Decrypts a value sealed by `seal`.
+///
+/// * keys: Keys to try in order
+/// * k: Key of the value
+/// * v: Sealed value
+///
+/// Returns: Plaintext value
+///
+pub fn unseal(keys: &[Key], k: &str, v: &str) -> io::Result<String> {
+    let invalid = || io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed sealed value for '{}'", k));
+
+    let b = sealed_bytes(v).ok_or_else(invalid)?;
+    let (nonce, sealed) = b.split_at(NONCE_LEN);
+    let plain = keys.iter()
+        .filter_map(|key| XChaCha20Poly1305::new(&key.0.into())
+            .decrypt(nonce.into(), Payload { msg: sealed, aad: k.as_bytes() })
+            .ok())
+        .next()
+        .ok_or(EncryptionError::Unauthenticated)?;
+
+    String::from_utf8(plain).map_err(|_| invalid())
+}
+
+/// Decodes the nonce and ciphertext of a sealed value.
+///
+/// * v: Sealed value
+///
+/// Returns: Bytes within an option, none if the value is not sealed
+///
+fn sealed_bytes(v: &str) -> Option<Vec<u8>> {
+    if !v.starts_with(SEALED_PREFIX) || !v.ends_with(']') {
+        return None;
+    }
+
+    BASE64.decode(&v[SEALED_PREFIX.len()..v.len() - 1]).ok()
+        .filter(|b| b.len() >= NONCE_LEN + TAG_LEN)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, FileDriver, FormatDriver, JsonDriver, JsonFormat, Map, MemoryMedium};
+    use driver::test_dir;
+    use super::*;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        m.insert("api_token".to_string(), "s3cr3t-value".to_string());
+        m.insert("user".to_string(), "Rincewind".to_string());
+        m
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("encrypted_save").join("store.json");
+        let key = Key::generate();
+
+        let mut d = EncryptedDriver::new(JsonDriver::new(&path), key.clone());
+        d.save(&make_map()).unwrap();
+
+        let b = fs::read(&path).unwrap();
+        assert!(b.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&b).contains("s3cr3t"));
+        assert_eq!(make_map(), d.load().unwrap());
+
+        let d = EncryptedDriver::with_provider(JsonDriver::new(&path), move || Ok(key.clone()));
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[test]
+    fn encrypts_any_medium() {
+        let m = FormatDriver::with(JsonFormat::new(), MemoryMedium::new());
+        let mut d = EncryptedDriver::new(m, Key::generate());
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+        use driver::PermissionPolicy;
+
+        let path = test_dir("encrypted_private").join("store.txt");
+        let key = Key::generate();
+        EncryptedDriver::new(FileDriver::new(&path), key.clone()).save(&make_map()).unwrap();
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+        let f = FileDriver::new(&path).permissions(PermissionPolicy::Deny);
+        let e = EncryptedDriver::new(f, key).load().unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+    }
+
+    #[test]
+    fn wrong_key_and_tampering_are_distinct() {
+        let path = test_dir("encrypted_tamper").join("store.txt");
+
+        let mut d = EncryptedDriver::new(FileDriver::new(&path), Key::generate());
+        d.save(&make_map()).unwrap();
+
+        let e = EncryptedDriver::new(FileDriver::new(&path), Key::generate()).load().unwrap_err();
+        assert_eq!(Some(&EncryptionError::Unauthenticated), EncryptionError::of(&e));
+
+        let mut b = fs::read(&path).unwrap();
+        let n = b.len();
+        b[n - 1] ^= 1;
+        fs::write(&path, &b).unwrap();
+        let e = d.load().unwrap_err();
+        assert_eq!(Some(&EncryptionError::Unauthenticated), EncryptionError::of(&e));
+
+        fs::write(&path, "a=1\n").unwrap();
+        let e = d.load().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert_eq!(None, EncryptionError::of(&e));
+    }
+
+    #[test]
+    fn rotate_keys() {
+        let path = test_dir("encrypted_rotate").join("store.txt");
+        let (old, new) = (Key::generate(), Key::generate());
+
+        EncryptedDriver::new(FileDriver::new(&path), old.clone()).save(&make_map()).unwrap();
+
+        let mut d = EncryptedDriver::new(FileDriver::new(&path), new.clone()).previous_key(old.clone());
+        assert_eq!(make_map(), d.load().unwrap());
+        d.rotate().unwrap();
+
+        assert_eq!(make_map(), EncryptedDriver::new(FileDriver::new(&path), new).load().unwrap());
+        assert!(EncryptedDriver::new(FileDriver::new(&path), old).load().is_err());
+    }
+
+    #[test]
+    fn key_sources() {
+        let key = Key::generate();
+        assert_eq!(key, Key::from_hex(&key.to_hex()).unwrap());
+
+        let path = test_dir("encrypted_keys").join("store.key");
+        fs::write(&path, format!("{}\n", key.to_hex())).unwrap();
+        assert_eq!(key, Key::from_file(&path).unwrap());
+
+        env::set_var("RUST_COOKIES_TEST_KEY", key.to_hex());
+        assert_eq!(key, Key::from_env("RUST_COOKIES_TEST_KEY").unwrap());
+
+        for bad in &["", "abc", &"g".repeat(64)] {
+            let e = Key::from_hex(bad).unwrap_err();
+            assert!(matches!(EncryptionError::of(&e), Some(&EncryptionError::Key(_))), "{}", bad);
+        }
+
+        assert!(Key::from_env("RUST_COOKIES_MISSING_KEY").is_err());
+        assert_eq!("Key(..)", format!("{:?}", key));
+    }
+
+    #[test]
+    fn seal_unseal() {
+        let key = Key::generate();
+        let v = seal(&key, "password", "caf\u{e9} = 1");
+        assert!(is_sealed(&v), "{}", v);
+        assert!(!v.contains("caf"));
+        assert_ne!(v, seal(&key, "password", "caf\u{e9} = 1"));
+
+        assert_eq!("caf\u{e9} = 1", unseal(&[Key::generate(), key.clone()], "password", &v).unwrap());
+
+        let e = unseal(::std::slice::from_ref(&key), "other", &v).unwrap_err();
+        assert_eq!(Some(&EncryptionError::Unauthenticated), EncryptionError::of(&e));
+        assert!(unseal(&[key], "password", "ENC[v1,!!]").is_err());
+
+        for v in &["ENC[x]", "ENC[v1,!!]", "ENC[v1,AAAA]", "ENC[v1,", "ENC[v2,abc]"] {
+            assert!(!is_sealed(v), "{}", v);
+        }
+    }
+}
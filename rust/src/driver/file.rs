@@ -0,0 +1,500 @@
+//!
+//! # File driver
+//!
+//! Reads and writes key value pairs as lines of a plain text file
+//! using the store's own format:
+//!
+//! r"^([_a-zA-Z][_a-zA-Z0-9]*)=([^\n]*)$"
+//!
+//! Blank lines and lines starting with '#' are ignored so files may
+//! carry comments.
+//!
+//! `TextFormat` encodes this format wherever the bytes are kept and a
+//! `FileDriver` keeps them in a file. New files are created readable
+//! and writable by their owner only. Loading can also check that no one
+//! else may modify the file, see `PermissionPolicy`.
+//!
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use driver::{Format, IOCheck, IOResult, LineComments, Map};
+use driver::medium::{FileMedium, FormatDriver};
+
+/// How a file driver reacts on load to a file that is writable by
+/// group or others, or owned by an unexpected user. Only checked on
+/// Unix.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionPolicy {
+
+    /// Nothing is checked.
+    Off,
+
+    /// Problems are passed to the driver's warning handler, or printed
+    /// to standard error without one, and the file is still loaded.
+    Warn,
+
+    /// Loading fails with a `PermissionDenied` error.
+    Deny
+}
+
+/// Receives warnings about a file, such as the problems found under
+/// `PermissionPolicy::Warn`.
+///
+pub type WarningHandler = Box<dyn Fn(&str)>;
+
+/// The store's own plain text format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct TextFormat;
+
+/// A driver that stores key value pairs within a plain text file.
+///
+pub type FileDriver = FormatDriver<TextFormat, FileMedium>;
+
+/// An exclusive lock held by creating a sibling '.lock' file next to
+/// the file it guards. A lock file left behind by a crashed process
+/// must be removed by hand.
+///
+pub struct LockFile {
+    path: PathBuf,
+    timeout: Duration,
+    held: bool
+}
+
+/// Implements factory methods.
+///
+impl TextFormat {
+
+    /// Creates the plain text format.
+    ///
+    pub fn new() -> TextFormat {
+        TextFormat
+    }
+}
+
+/// Implements format trait for the plain text format.
+///
+impl Format for TextFormat {
+
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        Ok(format(s).into_bytes())
+    }
+
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let text = String::from_utf8(b.to_vec()).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData, e.to_string()))?;
+        parse(&text)
+    }
+}
+
+/// Implements line comments for the text format, which ignores lines
+/// starting with '#'.
+///
+impl LineComments for TextFormat {}
+
+/// Implements factory methods.
+///
+impl FileDriver {
+
+    /// Creates a new file driver.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> FileDriver {
+        FormatDriver::with(TextFormat::new(), FileMedium::new(path))
+    }
+}
+
+/// Implements lock file methods.
+///
+impl LockFile {
+
+    /// Creates a lock for a file, not yet acquired. Acquiring waits at
+    /// most five seconds by default.
+    ///
+    /// * path: File to guard
+    ///
+    pub fn new(path: &Path) -> LockFile {
+        LockFile {
+            path: sibling(path, ".lock"),
+            timeout: Duration::from_secs(5),
+            held: false
+        }
+    }
+
+    /// Sets how long `acquire` waits before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn timeout(mut self, t: Duration) -> LockFile {
+        self.timeout = t;
+        self
+    }
+
+    /// Creates the lock file, waiting for any other holder to remove
+    /// it first. Does nothing if the lock is already held.
+    ///
+    pub fn acquire(&mut self) -> IOCheck {
+        if self.held {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&self.path) {
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if start.elapsed() >= self.timeout {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("Timed out waiting for lock '{}'", self.path.display())));
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e)
+            }
+        }
+
+        self.held = true;
+        Ok(())
+    }
+
+    /// Removes the lock file if the lock is held.
+    ///
+    pub fn release(&mut self) -> IOCheck {
+        if !self.held {
+            return Ok(());
+        }
+
+        self.held = false;
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Implements releasing a held lock when it is dropped.
+///
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+/// Parses the lines of a store file into a map.
+///
+/// * s: File content
+///
+/// Returns: Map of key value pairs
+///
+pub fn parse(s: &str) -> IOResult {
+    let re = Regex::new(r"^([_a-zA-Z][_a-zA-Z0-9]*)=([^\n]*)$").unwrap();
+    let mut m = Map::new();
+
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match re.captures(line) {
+            Some(c) => m.insert(c[1].to_string(), c[2].to_string()),
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid key value pair at line {}", i + 1)))
+        };
+    }
+
+    Ok(m)
+}
+
+/// Formats a map as the lines of a store file, sorted by key.
+///
+/// * s: Key value pairs
+///
+/// Returns: File content
+///
+pub fn format(s: &Map) -> String {
+    let mut keys: Vec<&String> = s.keys().collect();
+    keys.sort();
+
+    let mut out = String::new();
+    for k in keys {
+        out.push_str(k);
+        out.push('=');
+        out.push_str(&s[k]);
+        out.push('\n');
+    }
+    out
+}
+
+/// Writes a file by writing a temporary sibling then renaming it over
+/// the target so readers never observe a partial file. New files are
+/// readable and writable by their owner only.
+///
+/// * path: File to replace
+/// * bytes: New content
+///
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> IOCheck {
+    write_atomic_mode(path, bytes, 0o600)
+}
+
+/// Writes a file atomically like `write_atomic`. A replaced file keeps
+/// its permissions, a new file is given some.
+///
+/// * path: File to replace
+/// * bytes: New content
+/// * mode: Unix permission bits of a new file
+///
+pub fn write_atomic_mode(path: &Path, bytes: &[u8], mode: u32) -> IOCheck {
+    write_atomic_via(path, &sibling(path, ".tmp"), bytes, mode)
+}
+
+/// Writes a file atomically like `write_atomic_mode` through a chosen
+/// temporary file, which must be on the same file system. A temporary
+/// file left behind by an earlier write is replaced.
+///
+/// * path: File to replace
+/// * tmp: Temporary file renamed over the target
+/// * bytes: New content
+/// * mode: Unix permission bits of a new file
+///
+pub fn write_atomic_via(path: &Path, tmp: &Path, bytes: &[u8], mode: u32) -> IOCheck {
+    match fs::remove_file(tmp) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e)
+    }
+
+    {
+        let mut f = create_private(tmp, path, mode)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+    }
+    fs::rename(tmp, path)
+}
+
+/// Creates a temporary file with the permissions of the file it
+/// replaces, or a mode if there is none, so it is never more open than
+/// that while being written.
+///
+#[cfg(unix)]
+fn create_private(tmp: &Path, path: &Path, mode: u32) -> io::Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mode = match fs::metadata(path) {
+        Ok(m) => m.permissions().mode() & 0o7777,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => mode,
+        Err(e) => return Err(e)
+    };
+
+    let f = fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(tmp)?;
+    // The umask may have cleared bits the mode asks for
+    f.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(f)
+}
+
+#[cfg(not(unix))]
+fn create_private(tmp: &Path, _: &Path, _: u32) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(tmp)
+}
+
+/// Describes how a file could be modified by someone other than its
+/// expected owner.
+///
+/// * path: File to check, named within the problems
+/// * m: Metadata of the file, taken from an open handle so it describes
+///   the file read
+/// * owner: Expected owner, the effective user of the process if none
+///
+/// Returns: Problems found, each with a fix
+///
+#[cfg(unix)]
+pub fn permission_problems(path: &Path, m: &fs::Metadata, owner: Option<u32>) -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mode = m.mode() & 0o7777;
+    let mut problems = Vec::new();
+
+    if mode & 0o022 != 0 {
+        problems.push(format!(
+            "'{}' is writable by group or others (mode {:04o}, expected {:04o} or stricter); \
+             run 'chmod go-w {}'",
+            path.display(), mode, mode & !0o022, path.display()));
+    }
+
+    let expected = owner.unwrap_or_else(|| unsafe { ::libc::geteuid() });
+    if m.uid() != expected {
+        problems.push(format!(
+            "'{}' is owned by uid {} (expected uid {}); run 'chown {} {}'",
+            path.display(), m.uid(), expected, expected, path.display()));
+    }
+
+    problems
+}
+
+#[cfg(not(unix))]
+pub fn permission_problems(_: &Path, _: &fs::Metadata, _: Option<u32>) -> Vec<String> {
+    Vec::new()
+}
+
+/// Appends a suffix to the file name of a path.
+///
+/// * path: Path to extend
+/// * suffix: Suffix to append
+///
+pub fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::time::Duration;
+
+    use driver::{Driver, Map};
+    use driver::test_dir;
+    use super::{FileDriver, PermissionPolicy};
+
+    #[test]
+    fn load_missing_file_is_empty() {
+        let d = FileDriver::new(test_dir("file_missing").join("store.kvs"));
+        assert!(d.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("file_save_load").join("store.kvs");
+
+        let mut m = Map::new();
+        m.insert("b".to_string(), "Weatherwax".to_string());
+        m.insert("a".to_string(), "Rincewind".to_string());
+        m.insert("c".to_string(), "".to_string());
+
+        let mut d = FileDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!("a=Rincewind\nb=Weatherwax\nc=\n", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+
+    #[test]
+    fn load_skips_comments_and_blanks() {
+        let path = test_dir("file_comments").join("store.kvs");
+        fs::write(&path, "# heading\n\na=1\n  \nb=x=y\n").unwrap();
+
+        let m = FileDriver::new(&path).load().unwrap();
+        assert_eq!(2, m.len());
+        assert_eq!("1", m["a"]);
+        assert_eq!("x=y", m["b"]);
+    }
+
+    #[test]
+    fn load_reports_bad_line() {
+        let path = test_dir("file_bad_line").join("store.kvs");
+        fs::write(&path, "a=1\n1b=2\n").unwrap();
+
+        let e = FileDriver::new(&path).load().unwrap_err();
+        assert!(e.to_string().contains("line 2"), "{}", e);
+    }
+
+    #[test]
+    fn lock_excludes_other_writers() {
+        let path = test_dir("file_lock").join("store.kvs");
+
+        let mut a = FileDriver::new(&path);
+        let mut b = FileDriver::new(&path).lock_timeout(Duration::from_millis(50));
+
+        a.lock().unwrap();
+        assert!(b.lock().is_err());
+
+        a.unlock().unwrap();
+        b.lock().unwrap();
+        b.unlock().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("file_mode");
+        let mode = |p: &str| fs::metadata(dir.join(p)).unwrap().permissions().mode() & 0o777;
+
+        FileDriver::new(dir.join("new.kvs")).save(&Map::new()).unwrap();
+        assert_eq!(0o600, mode("new.kvs"));
+
+        FileDriver::new(dir.join("custom.kvs")).mode(0o640).save(&Map::new()).unwrap();
+        assert_eq!(0o640, mode("custom.kvs"));
+
+        fs::write(dir.join("old.kvs"), "").unwrap();
+        fs::set_permissions(dir.join("old.kvs"), fs::Permissions::from_mode(0o644)).unwrap();
+        FileDriver::new(dir.join("old.kvs")).save(&Map::new()).unwrap();
+        assert_eq!(0o644, mode("old.kvs"));
+
+        // A stale temporary file does not lend its mode to a new file
+        fs::write(dir.join("stale.kvs.tmp"), "").unwrap();
+        fs::set_permissions(dir.join("stale.kvs.tmp"), fs::Permissions::from_mode(0o666)).unwrap();
+        FileDriver::new(dir.join("stale.kvs")).save(&Map::new()).unwrap();
+        assert_eq!(0o600, mode("stale.kvs"));
+        assert!(!dir.join("stale.kvs.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_policy() {
+        use std::cell::RefCell;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use std::rc::Rc;
+
+        let path = test_dir("file_policy").join("store.kvs");
+        fs::write(&path, "a=1\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+        assert!(FileDriver::new(&path).load().is_ok());
+        assert!(FileDriver::new(&path).permissions(PermissionPolicy::Warn).load().is_ok());
+
+        let warnings = Rc::new(RefCell::new(Vec::new()));
+        let w = warnings.clone();
+        FileDriver::new(&path)
+            .permissions(PermissionPolicy::Warn)
+            .warn_with(move |msg| w.borrow_mut().push(msg.to_string()))
+            .load()
+            .unwrap();
+        assert_eq!(1, warnings.borrow().len());
+        assert!(warnings.borrow()[0].contains("chmod go-w"), "{:?}", warnings);
+
+        let e = FileDriver::new(&path).permissions(PermissionPolicy::Deny).load().unwrap_err();
+        assert_eq!(::std::io::ErrorKind::PermissionDenied, e.kind());
+        assert!(e.to_string().contains("mode 0666, expected 0644"), "{}", e);
+        assert!(e.to_string().contains("chmod go-w"), "{}", e);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(FileDriver::new(&path).permissions(PermissionPolicy::Deny).load().is_ok());
+
+        let uid = fs::metadata(&path).unwrap().uid();
+        let e = FileDriver::new(&path)
+            .permissions(PermissionPolicy::Deny)
+            .owner(uid + 1)
+            .load()
+            .unwrap_err();
+        assert!(e.to_string().contains(&format!("owned by uid {} (expected uid {})", uid, uid + 1)),
+                "{}", e);
+    }
+}
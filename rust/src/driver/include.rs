@@ -0,0 +1,402 @@
+//!
+//! # Include driver
+//!
+//! Reads key value pairs from a root file in the store's own format
+//! that may pull in other files with directives:
+//!
+//! include path/to/file.conf
+//! include_dir path/to/app.conf.d
+//!
+//! Relative paths are resolved against the directory of the including
+//! file and 'include_dir' reads every '*.conf' file in lexical order.
+//! Files are merged in the order they are read so later definitions
+//! override earlier ones. Saving writes each value back to the file it
+//! came from, new keys go to the root file.
+//!
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use regex::Regex;
+
+use driver::{Driver, IOCheck, IOResult, Map};
+use driver::file::{write_atomic_mode, LockFile};
+
+/// A driver that merges a root file with the files it includes.
+///
+pub struct IncludeDriver {
+    path: PathBuf,
+    mode: u32,
+    lock: LockFile
+}
+
+/// A file read while following includes. Lines removed on save are
+/// set to None.
+///
+struct Source {
+    path: PathBuf,
+    lines: Vec<Option<String>>,
+    modified: bool
+}
+
+/// Every file of an include tree along with where each key is defined.
+///
+struct Tree {
+    sources: Vec<Source>,
+    map: Map,
+    defs: HashMap<String, Vec<(usize, usize)>>
+}
+
+/// Implements factory methods.
+///
+impl IncludeDriver {
+
+    /// Creates a new include driver.
+    ///
+    /// * path: Path of the root file
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> IncludeDriver {
+        IncludeDriver {
+            path: path.as_ref().to_path_buf(),
+            mode: 0o600,
+            lock: LockFile::new(path.as_ref())
+        }
+    }
+
+    /// Sets the mode new files are created with, 0600 by default.
+    /// Existing files, included or not, keep their mode.
+    ///
+    /// * mode: Unix permission bits
+    ///
+    pub fn mode(mut self, mode: u32) -> IncludeDriver {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets how long `lock` waits for another writer to release the
+    /// root file before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn lock_timeout(mut self, t: Duration) -> IncludeDriver {
+        self.lock = self.lock.timeout(t);
+        self
+    }
+
+    /// Get the path of the root file.
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the root file and everything it includes. A missing root
+    /// file is treated as empty.
+    ///
+    fn read_tree(&self) -> io::Result<Tree> {
+        let mut t = Tree {
+            sources: Vec::new(),
+            map: Map::new(),
+            defs: HashMap::new()
+        };
+
+        if self.path.exists() {
+            let mut stack = Vec::new();
+            t.visit(&self.path, &mut stack)?;
+        } else {
+            t.sources.push(Source {
+                path: self.path.clone(),
+                lines: Vec::new(),
+                modified: false
+            });
+        }
+
+        Ok(t)
+    }
+}
+
+/// Implements reading include trees.
+///
+impl Tree {
+
+    /// Reads a file, following its includes depth first.
+    ///
+    /// * path: File to read
+    /// * stack: Files currently being read, to detect cycles
+    ///
+    fn visit(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> IOCheck {
+        let id = fs::canonicalize(path)?;
+
+        if stack.contains(&id) {
+            let mut cycle: Vec<String> = stack.iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            cycle.push(id.display().to_string());
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Include cycle {}", cycle.join(" -> "))));
+        }
+
+        let idx = match self.sources.iter().position(|s| s.path == id) {
+            Some(i) => i,
+            None => {
+                let text = fs::read_to_string(&id)?;
+                self.sources.push(Source {
+                    path: id.clone(),
+                    lines: text.lines().map(|l| Some(l.to_string())).collect(),
+                    modified: false
+                });
+                self.sources.len() - 1
+            }
+        };
+
+        stack.push(id.clone());
+
+        let entry = Regex::new(r"^([_a-zA-Z][_a-zA-Z0-9]*)=([^\n]*)$").unwrap();
+        let include = Regex::new(r"^(include|include_dir)\s+(.+?)\s*$").unwrap();
+        let dir = id.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let lines: Vec<String> = self.sources[idx].lines.iter()
+            .map(|l| l.clone().unwrap_or_default())
+            .collect();
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let at = |e: io::Error| io::Error::new(
+                e.kind(),
+                format!("{} at {}:{}", e, id.display(), i + 1));
+
+            if let Some(c) = include.captures(line) {
+                let target = dir.join(&c[2]);
+
+                if &c[1] == "include" {
+                    self.visit(&target, stack).map_err(at)?;
+                } else {
+                    for f in conf_files(&target).map_err(at)? {
+                        self.visit(&f, stack).map_err(at)?;
+                    }
+                }
+            } else if let Some(c) = entry.captures(line) {
+                self.map.insert(c[1].to_string(), c[2].to_string());
+                self.defs.entry(c[1].to_string()).or_default().push((idx, i));
+            } else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid key value pair at {}:{}", id.display(), i + 1)));
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+}
+
+/// Implements driver trait for the include driver.
+///
+impl Driver for IncludeDriver {
+
+    /// Reads and merges the root file and its includes.
+    ///
+    fn load(&self) -> IOResult {
+        Ok(self.read_tree()?.map)
+    }
+
+    /// Writes changed values back to the line that defines them and
+    /// removes every definition of removed keys. New keys are appended
+    /// to the root file. Only files that changed are rewritten.
+    ///
+    /// * s: Key value pairs
+    ///
+    fn save(&mut self, s: &Map) -> IOCheck {
+        let mut t = self.read_tree()?;
+
+        let mut keys: Vec<&String> = s.keys().collect();
+        keys.sort();
+
+        for k in keys {
+            let v = &s[k];
+            if t.map.get(k) == Some(v) {
+                continue;
+            }
+
+            let line = Some(format!("{}={}", k, v));
+            match t.defs.get(k).and_then(|d| d.last()) {
+                Some(&(f, l)) => {
+                    t.sources[f].lines[l] = line;
+                    t.sources[f].modified = true;
+                }
+                None => {
+                    t.sources[0].lines.push(line);
+                    t.sources[0].modified = true;
+                }
+            }
+        }
+
+        for (k, defs) in t.defs.iter() {
+            if !s.contains_key(k) {
+                for &(f, l) in defs {
+                    t.sources[f].lines[l] = None;
+                    t.sources[f].modified = true;
+                }
+            }
+        }
+
+        for src in t.sources.iter().filter(|s| s.modified) {
+            let mut out = String::new();
+            for l in src.lines.iter().flatten() {
+                out.push_str(l);
+                out.push('\n');
+            }
+            write_atomic_mode(&src.path, out.as_bytes(), self.mode)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the lock file, waiting for any other holder to remove
+    /// it first.
+    ///
+    fn lock(&mut self) -> IOCheck {
+        self.lock.acquire()
+    }
+
+    /// Removes the lock file.
+    ///
+    fn unlock(&mut self) -> IOCheck {
+        self.lock.release()
+    }
+}
+
+/// Lists the '*.conf' files of a directory in lexical order. A missing
+/// directory has no files.
+///
+fn conf_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e)
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let p = entry?.path();
+        if p.extension().is_some_and(|e| e == "conf") && p.is_file() {
+            files.push(p);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Map};
+    use driver::test_dir;
+    use super::IncludeDriver;
+
+    #[test]
+    fn later_files_override() {
+        let dir = test_dir("include_merge");
+        fs::create_dir(dir.join("app.conf.d")).unwrap();
+        fs::write(dir.join("app.conf"),
+                  "a=root\nb=root\nc=root\ninclude_dir app.conf.d\nd=root\n").unwrap();
+        fs::write(dir.join("app.conf.d").join("20-b.conf"), "b=20\nc=20\n").unwrap();
+        fs::write(dir.join("app.conf.d").join("10-a.conf"), "b=10\nd=10\n").unwrap();
+        fs::write(dir.join("app.conf.d").join("ignored.txt"), "c=txt\n").unwrap();
+
+        let m = IncludeDriver::new(dir.join("app.conf")).load().unwrap();
+        assert_eq!("root", m["a"]);
+        assert_eq!("20", m["b"]);
+        assert_eq!("20", m["c"]);
+        assert_eq!("root", m["d"]);
+    }
+
+    #[test]
+    fn include_is_relative_to_includer() {
+        let dir = test_dir("include_relative");
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("app.conf"), "include sub/one.conf\n").unwrap();
+        fs::write(dir.join("sub").join("one.conf"), "include two.conf\na=1\n").unwrap();
+        fs::write(dir.join("sub").join("two.conf"), "b=2\n").unwrap();
+
+        let m = IncludeDriver::new(dir.join("app.conf")).load().unwrap();
+        assert_eq!("1", m["a"]);
+        assert_eq!("2", m["b"]);
+    }
+
+    #[test]
+    fn cycle_is_detected() {
+        let dir = test_dir("include_cycle");
+        fs::write(dir.join("a.conf"), "x=1\ninclude b.conf\n").unwrap();
+        fs::write(dir.join("b.conf"), "include a.conf\n").unwrap();
+
+        let e = IncludeDriver::new(dir.join("a.conf")).load().unwrap_err();
+        let msg = e.to_string();
+        assert!(msg.contains("Include cycle"), "{}", msg);
+        assert!(msg.contains("b.conf:1"), "{}", msg);
+    }
+
+    #[test]
+    fn error_names_file_and_line() {
+        let dir = test_dir("include_error");
+        fs::write(dir.join("app.conf"), "include extra.conf\n").unwrap();
+        fs::write(dir.join("extra.conf"), "# comment\na=1\n2b=2\n").unwrap();
+
+        let e = IncludeDriver::new(dir.join("app.conf")).load().unwrap_err();
+        assert!(e.to_string().contains("extra.conf:3"), "{}", e);
+    }
+
+    #[test]
+    fn save_writes_back_to_origin() {
+        let dir = test_dir("include_save");
+        fs::create_dir(dir.join("app.conf.d")).unwrap();
+        fs::write(dir.join("app.conf"), "# root\na=1\ninclude_dir app.conf.d\n").unwrap();
+        fs::write(dir.join("app.conf.d").join("db.conf"), "host=x\nport=1\n").unwrap();
+        fs::write(dir.join("app.conf.d").join("other.conf"), "z=1\n").unwrap();
+
+        let mut d = IncludeDriver::new(dir.join("app.conf"));
+        let mut m = d.load().unwrap();
+        m.insert("port".to_string(), "2".to_string());
+        m.insert("new".to_string(), "3".to_string());
+        m.remove("host");
+        d.save(&m).unwrap();
+
+        assert_eq!("# root\na=1\ninclude_dir app.conf.d\nnew=3\n",
+                   fs::read_to_string(dir.join("app.conf")).unwrap());
+        assert_eq!("port=2\n",
+                   fs::read_to_string(dir.join("app.conf.d").join("db.conf")).unwrap());
+        assert_eq!(m, d.load().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn new_root_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("include_mode");
+        let mode = |p: &str| fs::metadata(dir.join(p)).unwrap().permissions().mode() & 0o777;
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), "1".to_string());
+        IncludeDriver::new(dir.join("new.conf")).save(&m).unwrap();
+        assert_eq!(0o600, mode("new.conf"));
+
+        IncludeDriver::new(dir.join("custom.conf")).mode(0o640).save(&m).unwrap();
+        assert_eq!(0o640, mode("custom.conf"));
+    }
+}
@@ -0,0 +1,309 @@
+//!
+//! # INI driver
+//!
+//! Reads and writes key value pairs as an INI file where each key is
+//! prefixed by its section so
+//!
+//! [db]
+//! host = x
+//!
+//! becomes 'db_host=x'. Keys before the first section have no prefix.
+//! Lines starting with ';' or '#' are comments, as is anything after a
+//! ';' or '#' preceded by whitespace in an unquoted value. Values may
+//! be double quoted, with '\"' and '\\' escapes, or single quoted and
+//! taken literally. On save keys are regrouped into sections by the
+//! text before their first '_'.
+//!
+//! `IniFormat` encodes INI wherever the bytes are kept and an
+//! `IniDriver` keeps them in a file.
+//!
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use driver::{Format, IOResult, LineComments, Map};
+use driver::medium::{FileMedium, FormatDriver};
+use driver::quote::INI;
+use Store;
+
+/// How a key defined more than once is handled when loading.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicates {
+    Error,
+    FirstWins,
+    LastWins
+}
+
+/// The INI format.
+///
+#[derive(Debug, Clone)]
+pub struct IniFormat {
+    duplicates: Duplicates
+}
+
+/// A driver that stores key value pairs within an INI file.
+///
+pub type IniDriver = FormatDriver<IniFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl IniFormat {
+
+    /// Creates the INI format. Duplicate keys are an error by default.
+    ///
+    pub fn new() -> IniFormat {
+        IniFormat { duplicates: Duplicates::Error }
+    }
+
+    /// Sets how duplicate keys are handled when decoding.
+    ///
+    /// * d: Duplicate key policy
+    ///
+    pub fn duplicates(mut self, d: Duplicates) -> IniFormat {
+        self.duplicates = d;
+        self
+    }
+}
+
+/// Implements the default INI format.
+///
+impl Default for IniFormat {
+    fn default() -> IniFormat {
+        IniFormat::new()
+    }
+}
+
+/// Implements format trait for the INI format.
+///
+impl Format for IniFormat {
+
+    /// Parses INI text into prefixed key value pairs.
+    ///
+    /// * b: INI text
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let text = String::from_utf8(b.to_vec()).map_err(|e| invalid(e.to_string()))?;
+        let mut m = Map::new();
+        let mut prefix = String::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let end = line.find(']').ok_or_else(|| invalid(format!(
+                    "Unclosed section at line {}", n)))?;
+                if !INI.is_comment(&line[end + 1..]) {
+                    return Err(invalid(format!("Invalid section at line {}", n)));
+                }
+
+                let name = line[1..end].trim();
+                prefix = if name.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}_", name)
+                };
+                continue;
+            }
+
+            let eq = line.find('=').ok_or_else(|| invalid(format!(
+                "Invalid key value pair at line {}", n)))?;
+
+            let key = format!("{}{}", prefix, line[..eq].trim());
+            if !Store::check_key(&key) {
+                return Err(invalid(format!("Invalid key '{}' at line {}", key, n)));
+            }
+
+            let value = INI.unquote(&line[eq + 1..]).map_err(|e| invalid(format!(
+                "{} at line {}", e, n)))?;
+
+            if m.contains_key(&key) {
+                match self.duplicates {
+                    Duplicates::Error => return Err(invalid(format!(
+                        "Duplicate key '{}' at line {}", key, n))),
+                    Duplicates::FirstWins => continue,
+                    Duplicates::LastWins => ()
+                }
+            }
+
+            m.insert(key, value);
+        }
+
+        Ok(m)
+    }
+
+    /// Formats key value pairs as INI text, grouping keys into sections
+    /// by the text before their first '_'.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: INI text
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        let mut sections: BTreeMap<&str, BTreeMap<&str, &String>> = BTreeMap::new();
+
+        for (k, v) in s.iter() {
+            let (section, key) = match k.find('_') {
+                Some(i) if i > 0 && i + 1 < k.len() => (&k[..i], &k[i + 1..]),
+                _ => ("", &k[..])
+            };
+            sections.entry(section).or_default().insert(key, v);
+        }
+
+        let mut out = String::new();
+        for (section, pairs) in sections.iter() {
+            if !section.is_empty() {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                out.push_str(&format!("[{}]\n", section));
+            }
+
+            for (k, v) in pairs.iter() {
+                out.push_str(&format!("{}={}\n", k, quote(v)));
+            }
+        }
+
+        Ok(out.into_bytes())
+    }
+}
+
+/// Implements line comments for the INI format, which also accepts
+/// ';'.
+///
+impl LineComments for IniFormat {}
+
+/// Implements factory methods.
+///
+impl IniDriver {
+
+    /// Creates a new INI driver. Duplicate keys are an error by
+    /// default.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> IniDriver {
+        FormatDriver::with(IniFormat::new(), FileMedium::new(path))
+    }
+
+    /// Sets how duplicate keys are handled when loading.
+    ///
+    /// * d: Duplicate key policy
+    ///
+    pub fn duplicates(mut self, d: Duplicates) -> IniDriver {
+        self.format_mut().duplicates = d;
+        self
+    }
+}
+
+/// Quotes a value if it would not otherwise read back unchanged.
+///
+fn quote(v: &str) -> String {
+    let plain = INI.unquote(v).ok().is_some_and(|u| u == v);
+    if plain {
+        return v.to_string();
+    }
+
+    format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Format, Map};
+    use driver::test_dir;
+    use super::{Duplicates, IniDriver, IniFormat};
+
+    fn decode(d: &IniFormat, s: &str) -> ::driver::IOResult {
+        d.decode(s.as_bytes())
+    }
+
+    #[test]
+    fn sections_become_prefixes() {
+        let m = decode(&IniFormat::new(), r#"
+; legacy settings
+name = app
+# another comment
+
+[db]
+host = db.local   ; inline comment
+pass = "a;b # c"
+path = 'C:\temp'
+quote = "say \"hi\""
+empty =
+url = http://x/#frag
+        "#).unwrap();
+
+        assert_eq!(7, m.len());
+        assert_eq!("app", m["name"]);
+        assert_eq!("db.local", m["db_host"]);
+        assert_eq!("a;b # c", m["db_pass"]);
+        assert_eq!("C:\\temp", m["db_path"]);
+        assert_eq!("say \"hi\"", m["db_quote"]);
+        assert_eq!("", m["db_empty"]);
+        assert_eq!("http://x/#frag", m["db_url"]);
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let ini = "[a]\nb = 1\n[a]\nb = 2\n";
+
+        let e = decode(&IniFormat::new(), ini).unwrap_err();
+        assert!(e.to_string().contains("line 4"), "{}", e);
+
+        let d = IniFormat::new().duplicates(Duplicates::FirstWins);
+        assert_eq!("1", decode(&d, ini).unwrap()["a_b"]);
+
+        let d = IniFormat::new().duplicates(Duplicates::LastWins);
+        assert_eq!("2", decode(&d, ini).unwrap()["a_b"]);
+    }
+
+    #[test]
+    fn invalid_lines_fail() {
+        let d = IniFormat::new();
+        for ini in &["[db\nx=1", "no separator", "[db]\nbad-key = 1",
+                     "a = \"open", "a = \"x\" y", "a = \"\\n\""] {
+            assert!(decode(&d, ini).is_err(), "{} should fail", ini);
+        }
+    }
+
+    #[test]
+    fn save_regroups_sections() {
+        let path = test_dir("ini_save").join("store.ini");
+
+        let mut m = Map::new();
+        m.insert("name".to_string(), "app".to_string());
+        m.insert("db_host".to_string(), "x".to_string());
+        m.insert("db_pass".to_string(), " a;b \"c\" ".to_string());
+        m.insert("log_level".to_string(), "debug".to_string());
+
+        let mut d = IniDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!("name=app\n\n[db]\nhost=x\npass=\" a;b \\\"c\\\" \"\n\n[log]\nlevel=debug\n", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+}
@@ -0,0 +1,244 @@
+//!
+//! # JSON driver
+//!
+//! Reads and writes key value pairs as a flat JSON object:
+//!
+//! { "key": "value", ... }
+//!
+//! Numbers and booleans are converted to strings the typed getters can
+//! parse back. Nested objects may optionally be flattened into keys
+//! joined with '_', e.g. { "db": { "host": "x" } } becomes 'db_host'.
+//! Values are always saved as strings in a flat object.
+//!
+//! `JsonFormat` encodes JSON wherever the bytes are kept and a
+//! `JsonDriver` keeps them in a file.
+//!
+
+use std::io;
+use std::path::Path;
+
+use serde_json;
+use serde_json::Value;
+
+use driver::{Format, IOCheck, IOResult, Map};
+use driver::medium::{FileMedium, FormatDriver};
+use Store;
+
+/// The JSON format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct JsonFormat {
+    flatten: bool
+}
+
+/// A driver that stores key value pairs within a JSON file.
+///
+pub type JsonDriver = FormatDriver<JsonFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl JsonFormat {
+
+    /// Creates the JSON format. Nested objects are rejected unless
+    /// flattening is enabled.
+    ///
+    pub fn new() -> JsonFormat {
+        JsonFormat { flatten: false }
+    }
+
+    /// Sets whether nested objects are flattened into 'parent_child'
+    /// keys when decoding.
+    ///
+    /// * f: True to flatten nested objects
+    ///
+    pub fn flatten(mut self, f: bool) -> JsonFormat {
+        self.flatten = f;
+        self
+    }
+
+    /// Adds the members of an object to a map, prefixing their keys.
+    ///
+    fn collect(&self, m: &mut Map, prefix: &str, o: &serde_json::Map<String, Value>) -> IOCheck {
+        for (k, v) in o.iter() {
+            let key = format!("{}{}", prefix, k);
+
+            let s = match *v {
+                Value::String(ref s) => s.clone(),
+                Value::Number(ref n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                Value::Object(ref o) if self.flatten => {
+                    self.collect(m, &format!("{}_", key), o)?;
+                    continue;
+                }
+                Value::Object(_) => return Err(invalid(format!(
+                    "Nested object '{}' cannot be represented without flattening", key))),
+                Value::Array(_) => return Err(invalid(format!(
+                    "Array '{}' cannot be represented as a value", key))),
+                Value::Null => return Err(invalid(format!(
+                    "Null '{}' cannot be represented as a value", key)))
+            };
+
+            if !Store::check_key(&key) {
+                return Err(invalid(format!("Invalid key '{}'", key)));
+            }
+
+            if !Store::check_value(&s) {
+                return Err(invalid(format!("Invalid value for '{}'", key)));
+            }
+
+            if m.insert(key.clone(), s).is_some() {
+                return Err(invalid(format!("Duplicate key '{}' after flattening", key)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Implements format trait for the JSON format.
+///
+impl Format for JsonFormat {
+
+    /// Parses a JSON object into key value pairs.
+    ///
+    /// * b: JSON text
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let v: Value = serde_json::from_slice(b).map_err(|e| invalid(format!(
+            "Invalid JSON: {}", e)))?;
+
+        match v {
+            Value::Object(o) => {
+                let mut m = Map::new();
+                self.collect(&mut m, "", &o)?;
+                Ok(m)
+            }
+            _ => Err(invalid("JSON must be an object of key value pairs".to_string()))
+        }
+    }
+
+    /// Formats key value pairs as a JSON object of strings, sorted by
+    /// key.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: JSON text
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        let o: serde_json::Map<String, Value> = s.iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+
+        let mut b = serde_json::to_vec_pretty(&Value::Object(o))
+            .map_err(|e| invalid(e.to_string()))?;
+        b.push(b'\n');
+        Ok(b)
+    }
+}
+
+/// Implements factory methods.
+///
+impl JsonDriver {
+
+    /// Creates a new JSON driver. Nested objects are rejected unless
+    /// flattening is enabled.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> JsonDriver {
+        FormatDriver::with(JsonFormat::new(), FileMedium::new(path))
+    }
+
+    /// Sets whether nested objects are flattened into 'parent_child'
+    /// keys when loading.
+    ///
+    /// * f: True to flatten nested objects
+    ///
+    pub fn flatten(mut self, f: bool) -> JsonDriver {
+        self.format_mut().flatten = f;
+        self
+    }
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Format, Map};
+    use driver::test_dir;
+    use super::{JsonDriver, JsonFormat};
+    use Store;
+
+    fn decode(f: &JsonFormat, s: &str) -> ::driver::IOResult {
+        f.decode(s.as_bytes())
+    }
+
+    #[test]
+    fn scalars_become_strings() {
+        let d = JsonFormat::new();
+        let m = decode(&d, r#"{"name": "x", "port": 8080, "neg": -3,
+                               "ratio": 0.5, "on": true}"#).unwrap();
+
+        let s = Store::from(m);
+        assert_eq!(Some(&"x".to_string()), s.get("name"));
+        assert_eq!(8080, s.get_i64("port").unwrap().unwrap());
+        assert_eq!(-3, s.get_i64("neg").unwrap().unwrap());
+        assert_eq!(0.5, s.get_f64("ratio").unwrap().unwrap());
+        assert!(s.get_bool("on").unwrap().unwrap());
+    }
+
+    #[test]
+    fn nested_objects_flatten() {
+        let json = r#"{"db": {"host": "x", "pool": {"size": 4}}, "a": "1"}"#;
+
+        let e = decode(&JsonFormat::new(), json).unwrap_err();
+        assert!(e.to_string().contains("'db'"), "{}", e);
+
+        let m = decode(&JsonFormat::new().flatten(true), json).unwrap();
+        assert_eq!(3, m.len());
+        assert_eq!("x", m["db_host"]);
+        assert_eq!("4", m["db_pool_size"]);
+    }
+
+    #[test]
+    fn unrepresentable_values_fail() {
+        let d = JsonFormat::new().flatten(true);
+
+        for json in &[r#"["a"]"#, r#"{"a": null}"#, r#"{"a": [1, 2]}"#,
+                      r#"{"a-b": "1"}"#, r#"{"a": "x\ny"}"#,
+                      r#"{"a_b": "1", "a": {"b": "2"}}"#, "{"] {
+            assert!(decode(&d, json).is_err(), "{} should fail", json);
+        }
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("json_save_load").join("store.json");
+
+        let mut m = Map::new();
+        m.insert("b".to_string(), "Weatherwax".to_string());
+        m.insert("a".to_string(), "1".to_string());
+
+        let mut d = JsonDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!("{\n  \"a\": \"1\",\n  \"b\": \"Weatherwax\"\n}\n", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+}
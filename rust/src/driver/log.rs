@@ -0,0 +1,460 @@
+//!
+//! # Log driver
+//!
+//! Appends one record per write to a journal file rather than
+//! rewriting every key value pair:
+//!
+//! set key=value
+//! del key
+//!
+//! Loading replays the journal from the start. Once the journal grows
+//! past a ratio of the live data it is compacted into one 'set' record
+//! per live pair. A final record without its terminating newline was
+//! torn by a crash while being written and is dropped.
+//!
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::Duration;
+
+use driver::{Changes, Driver, IOCheck, IOResult, Map, Op};
+use driver::{apply_to, batch};
+use driver::file::{write_atomic_mode, LockFile};
+
+/// A driver that journals writes to an append only file.
+///
+pub struct LogDriver {
+    path: PathBuf,
+    compact_ratio: f64,
+    live_bytes: u64,
+    mode: u32,
+    lock: LockFile
+}
+
+/// Implements factory methods.
+///
+impl LogDriver {
+
+    /// Creates a new log driver. The journal is compacted once it is
+    /// more than twice the size of the live data by default. Until the
+    /// journal is next replayed the size it has now stands in for the
+    /// live data.
+    ///
+    /// * path: Path of the journal file
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> LogDriver {
+        LogDriver {
+            path: path.as_ref().to_path_buf(),
+            compact_ratio: 2.0,
+            live_bytes: fs::metadata(path.as_ref()).map_or(0, |md| md.len()),
+            mode: 0o600,
+            lock: LockFile::new(path.as_ref())
+        }
+    }
+
+    /// Sets how many times larger than the live data the journal may
+    /// grow before it is compacted.
+    ///
+    /// * r: Ratio of journal size to live data size
+    ///
+    pub fn compact_ratio(mut self, r: f64) -> LogDriver {
+        self.compact_ratio = r;
+        self
+    }
+
+    /// Sets the mode a new journal is created with, 0600 by default.
+    /// An existing journal keeps its mode.
+    ///
+    /// * mode: Unix permission bits
+    ///
+    pub fn mode(mut self, mode: u32) -> LogDriver {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets how long `lock` waits for another writer to release the
+    /// journal before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn lock_timeout(mut self, t: Duration) -> LogDriver {
+        self.lock = self.lock.timeout(t);
+        self
+    }
+
+    /// Get the path of the journal file.
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replays the journal and rewrites it as a snapshot of the live
+    /// key value pairs.
+    ///
+    pub fn compact(&mut self) -> IOCheck {
+        let m = self.load()?;
+        self.write_snapshot(&m)
+    }
+
+    /// Replaces the journal with one 'set' record per pair.
+    ///
+    fn write_snapshot(&mut self, m: &Map) -> IOCheck {
+        let mut keys: Vec<&String> = m.keys().collect();
+        keys.sort();
+
+        let mut out = String::new();
+        for k in keys {
+            record(&mut out, &Op::Put(k.clone(), m[k].clone()));
+        }
+
+        write_atomic_mode(&self.path, out.as_bytes(), self.mode)?;
+        self.live_bytes = out.len() as u64;
+        Ok(())
+    }
+
+    /// Appends records to the journal in a single write, first cutting
+    /// off any torn record so it cannot merge with the new ones.
+    ///
+    fn append(&mut self, b: &[Op]) -> IOCheck {
+        if b.is_empty() {
+            return Ok(());
+        }
+
+        let mut out = String::new();
+        for op in b {
+            record(&mut out, op);
+        }
+
+        let mut opts = fs::OpenOptions::new();
+        opts.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(self.mode);
+        }
+        let mut f = opts.open(&self.path)?;
+
+        let len = f.seek(SeekFrom::End(0))?;
+        if len > 0 {
+            let mut last = [0u8];
+            f.seek(SeekFrom::End(-1))?;
+            f.read_exact(&mut last)?;
+
+            if last[0] != b'\n' {
+                let mut bytes = Vec::new();
+                f.seek(SeekFrom::Start(0))?;
+                f.read_to_end(&mut bytes)?;
+
+                let end = bytes.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+                f.set_len(end as u64)?;
+            }
+        }
+
+        f.seek(SeekFrom::End(0))?;
+        f.write_all(out.as_bytes())?;
+        f.sync_data()
+    }
+
+    /// Checks whether the journal has outgrown the live data.
+    ///
+    fn oversized(&self) -> io::Result<bool> {
+        let size = match fs::metadata(&self.path) {
+            Ok(md) => md.len(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e)
+        };
+        Ok(size as f64 > self.compact_ratio * self.live_bytes as f64)
+    }
+
+    /// Compacts the journal if it appears to have outgrown the live
+    /// data. The live size is only an estimate between compactions so
+    /// the journal is replayed to confirm first.
+    ///
+    fn maybe_compact(&mut self) -> IOCheck {
+        if !self.oversized()? {
+            return Ok(());
+        }
+
+        let m = self.load()?;
+        self.live_bytes = snapshot_len(&m);
+
+        if self.oversized()? {
+            self.write_snapshot(&m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Implements driver trait for the log driver.
+///
+impl Driver for LogDriver {
+
+    /// Replays the journal. A missing journal is treated as an empty
+    /// store.
+    ///
+    fn load(&self) -> IOResult {
+        match fs::read(&self.path) {
+            Ok(b) => replay(&b),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Map::new()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Rewrites the journal as a snapshot of the key value pairs.
+    ///
+    /// * s: Key value pairs
+    ///
+    fn save(&mut self, s: &Map) -> IOCheck {
+        self.write_snapshot(s)
+    }
+
+    /// Appends a record per change, compacting against the supplied
+    /// pairs if the journal has grown too large.
+    ///
+    /// * s: Key value pairs
+    /// * c: Changed keys
+    ///
+    fn save_changes(&mut self, s: &Map, c: &Changes) -> IOCheck {
+        self.append(&batch(s, c))?;
+        self.live_bytes = snapshot_len(s);
+
+        if self.oversized()? {
+            self.write_snapshot(s)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a 'set' record.
+    ///
+    fn put(&mut self, k: &str, v: &str) -> IOCheck {
+        self.apply(&[Op::Put(k.to_string(), v.to_string())])
+    }
+
+    /// Appends a 'del' record.
+    ///
+    fn delete(&mut self, k: &str) -> IOCheck {
+        self.apply(&[Op::Delete(k.to_string())])
+    }
+
+    /// Appends a record per write.
+    ///
+    fn apply(&mut self, b: &[Op]) -> IOCheck {
+        self.append(b)?;
+        self.maybe_compact()
+    }
+
+    /// Creates the lock file, waiting for any other holder to remove
+    /// it first.
+    ///
+    fn lock(&mut self) -> IOCheck {
+        self.lock.acquire()
+    }
+
+    /// Removes the lock file.
+    ///
+    fn unlock(&mut self) -> IOCheck {
+        self.lock.release()
+    }
+}
+
+/// Replays journal records into a map. An unterminated final record is
+/// dropped as torn.
+///
+/// * b: Journal content
+///
+/// Returns: Map of live key value pairs
+///
+pub fn replay(b: &[u8]) -> IOResult {
+    let mut m = Map::new();
+
+    // A torn record may end part way through a character so it is cut
+    // off before decoding
+    let end = b.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+    let complete = str::from_utf8(&b[..end])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    for (i, line) in complete.lines().enumerate() {
+        let op = if let Some(kv) = line.strip_prefix("set ") {
+            kv.find('=').map(|j| Op::Put(kv[..j].to_string(), kv[j + 1..].to_string()))
+        } else {
+            line.strip_prefix("del ").map(|k| Op::Delete(k.to_string()))
+        };
+
+        match op {
+            Some(op) => apply_to(&mut m, &[op]),
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid journal record at line {}", i + 1)))
+        }
+    }
+
+    Ok(m)
+}
+
+/// Appends the journal record for a write.
+///
+fn record(out: &mut String, op: &Op) {
+    match *op {
+        Op::Put(ref k, ref v) => {
+            out.push_str("set ");
+            out.push_str(k);
+            out.push('=');
+            out.push_str(v);
+        }
+        Op::Delete(ref k) => {
+            out.push_str("del ");
+            out.push_str(k);
+        }
+    }
+    out.push('\n');
+}
+
+/// Size in bytes of the snapshot of some key value pairs.
+///
+fn snapshot_len(m: &Map) -> u64 {
+    m.iter().map(|(k, v)| (k.len() + v.len() + 6) as u64).sum()
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+    use std::io::Write;
+
+    use driver::{Driver, Map, Op};
+    use driver::test_dir;
+    use super::LogDriver;
+    use Store;
+
+    #[test]
+    fn append_and_replay() {
+        let path = test_dir("log_replay").join("store.log");
+        let mut d = LogDriver::new(&path).compact_ratio(100.0);
+
+        d.put("a", "1").unwrap();
+        d.put("b", "x=y").unwrap();
+        d.apply(&[Op::Put("a".to_string(), "2".to_string()), Op::Delete("c".to_string())]).unwrap();
+        d.delete("b").unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!("set a=1\nset b=x=y\nset a=2\ndel c\ndel b\n", text);
+
+        let m = d.load().unwrap();
+        assert_eq!(1, m.len());
+        assert_eq!("2", m["a"]);
+    }
+
+    #[test]
+    fn torn_record_is_dropped() {
+        let path = test_dir("log_torn").join("store.log");
+        // Torn part way through a two byte character
+        fs::write(&path, "set a=1\nset b=2\nset c=\u{e9}".as_bytes().split_last().unwrap().1).unwrap();
+
+        let mut d = LogDriver::new(&path).compact_ratio(100.0);
+        let m = d.load().unwrap();
+        assert_eq!(2, m.len());
+        assert!(!m.contains_key("c"));
+
+        // The torn record is cut off before appending
+        d.put("d", "4").unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!("set a=1\nset b=2\nset d=4\n", text);
+    }
+
+    #[test]
+    fn corrupt_record_is_invalid() {
+        let path = test_dir("log_corrupt").join("store.log");
+        fs::write(&path, "set a=1\nbogus\nset b=2\n").unwrap();
+
+        let e = LogDriver::new(&path).load().unwrap_err();
+        assert!(e.to_string().contains("line 2"), "{}", e);
+    }
+
+    #[test]
+    fn compacts_when_oversized() {
+        let path = test_dir("log_compact").join("store.log");
+        let mut d = LogDriver::new(&path);
+
+        for i in 0..100 {
+            d.put("a", &i.to_string()).unwrap();
+        }
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.lines().count() <= 2, "{}", text);
+        assert_eq!("99", d.load().unwrap()["a"]);
+    }
+
+    #[test]
+    fn fresh_driver_keeps_small_journal() {
+        let path = test_dir("log_fresh").join("store.log");
+        fs::write(&path, "set a=1\nset a=2\nset a=3\nset a=4\nset a=5\n").unwrap();
+
+        // A fresh driver must not take the live data to be empty, which
+        // would make every journal look oversized
+        let mut d = LogDriver::new(&path).compact_ratio(6.0);
+        assert_eq!(40, d.live_bytes);
+        d.put("b", "1").unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(6, text.lines().count(), "{}", text);
+    }
+
+    #[test]
+    fn store_appends_changes() {
+        let path = test_dir("log_store").join("store.log");
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), "1".to_string());
+        m.insert("b".to_string(), "2".to_string());
+        LogDriver::new(&path).save(&m).unwrap();
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(LogDriver::new(&path).compact_ratio(100.0)));
+        s.load().unwrap();
+        s.set("a", "3").unwrap();
+        s.save_if_dirty().unwrap();
+
+        let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(b"set b=").unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("set a=1\nset b=2\n"), "{}", text);
+        assert!(text.contains("set a=3\n"), "{}", text);
+
+        s.load().unwrap();
+        assert_eq!(Some(&"3".to_string()), s.get("a"));
+        assert_eq!(Some(&"2".to_string()), s.get("b"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn journal_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("log_mode");
+        let mode = |p: &str| fs::metadata(dir.join(p)).unwrap().permissions().mode() & 0o777;
+
+        LogDriver::new(dir.join("new.log")).put("a", "1").unwrap();
+        assert_eq!(0o600, mode("new.log"));
+
+        let mut d = LogDriver::new(dir.join("custom.log")).mode(0o640);
+        d.put("a", "1").unwrap();
+        assert_eq!(0o640, mode("custom.log"));
+        d.compact().unwrap();
+        assert_eq!(0o640, mode("custom.log"));
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), "1".to_string());
+        LogDriver::new(dir.join("snapshot.log")).mode(0o640).save(&m).unwrap();
+        assert_eq!(0o640, mode("snapshot.log"));
+    }
+}
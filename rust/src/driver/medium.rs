@@ -0,0 +1,590 @@
+//!
+//! # Storage media
+//!
+//! Media read and write the bytes of a store without knowing how key
+//! value pairs are encoded, that is left to a `Format`. A
+//! `FormatDriver` combines any format with any medium so, for example,
+//! JSON may be kept in a file, a memory buffer or sent over a socket
+//! without a driver for each. The file drivers, such as `JsonDriver`,
+//! are format drivers over a `FileMedium`.
+//!
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use driver::{ByteDriver, Driver, Format, IOCheck, IOResult, LineComments, Map};
+use driver::file::{permission_problems, write_atomic_mode, LockFile, PermissionPolicy, WarningHandler};
+
+/// Media that hold the encoded bytes of a store.
+///
+pub trait Medium {
+
+    /// Reads all bytes held.
+    ///
+    /// Returns: Bytes within an option, none if nothing has been
+    /// written yet
+    ///
+    fn read(&self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replaces all bytes held.
+    ///
+    /// * b: Bytes to write
+    ///
+    fn write(&mut self, b: &[u8]) -> IOCheck;
+
+    /// Acquires exclusive access to the medium. Media that are not
+    /// shared need not override this.
+    ///
+    fn lock(&mut self) -> IOCheck {
+        Ok(())
+    }
+
+    /// Releases exclusive access acquired by `lock`.
+    ///
+    fn unlock(&mut self) -> IOCheck {
+        Ok(())
+    }
+}
+
+/// A driver that encodes key value pairs with a format and keeps the
+/// bytes within a medium.
+///
+pub struct FormatDriver<F: Format, M: Medium> {
+    format: F,
+    medium: M
+}
+
+/// Implements factory methods.
+///
+impl<F: Format, M: Medium> FormatDriver<F, M> {
+
+    /// Creates a new format driver.
+    ///
+    /// * format: Encodes and decodes key value pairs
+    /// * medium: Holds the encoded bytes
+    ///
+    pub fn with(format: F, medium: M) -> FormatDriver<F, M> {
+        FormatDriver { format, medium }
+    }
+
+    /// Get the format.
+    ///
+    pub fn format(&self) -> &F {
+        &self.format
+    }
+
+    /// Get the format to change its settings.
+    ///
+    pub fn format_mut(&mut self) -> &mut F {
+        &mut self.format
+    }
+
+    /// Get the medium.
+    ///
+    pub fn medium(&self) -> &M {
+        &self.medium
+    }
+
+    /// Splits the driver into its format and medium, so either may be
+    /// wrapped.
+    ///
+    /// Returns: Format and medium
+    ///
+    pub fn into_parts(self) -> (F, M) {
+        (self.format, self.medium)
+    }
+}
+
+/// Implements settings of format drivers keeping their bytes in a
+/// file, shared by every file driver.
+///
+impl<F: Format> FormatDriver<F, FileMedium> {
+
+    /// Sets how the file's permissions and owner are checked on load.
+    /// Off by default.
+    ///
+    /// * p: Permission policy
+    ///
+    pub fn permissions(self, p: PermissionPolicy) -> FormatDriver<F, FileMedium> {
+        FormatDriver { format: self.format, medium: self.medium.permissions(p) }
+    }
+
+    /// Sets the user expected to own the file, the effective user of
+    /// the process by default.
+    ///
+    /// * uid: Expected owner
+    ///
+    pub fn owner(self, uid: u32) -> FormatDriver<F, FileMedium> {
+        FormatDriver { format: self.format, medium: self.medium.owner(uid) }
+    }
+
+    /// Sets the mode new files are created with, 0600 by default.
+    /// Existing files keep their mode.
+    ///
+    /// * mode: Unix permission bits
+    ///
+    pub fn mode(self, mode: u32) -> FormatDriver<F, FileMedium> {
+        FormatDriver { format: self.format, medium: self.medium.mode(mode) }
+    }
+
+    /// Sets how long `lock` waits for another writer to release the
+    /// file before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn lock_timeout(self, t: Duration) -> FormatDriver<F, FileMedium> {
+        FormatDriver { format: self.format, medium: self.medium.lock_timeout(t) }
+    }
+
+    /// Sets a function receiving warnings, such as the permission
+    /// problems found under `PermissionPolicy::Warn`. Warnings are
+    /// printed to standard error by default.
+    ///
+    /// * f: Receives each warning
+    ///
+    pub fn warn_with<W>(self, f: W) -> FormatDriver<F, FileMedium>
+        where W: Fn(&str) + 'static {
+        FormatDriver { format: self.format, medium: self.medium.warn_with(f) }
+    }
+
+    /// Get the path of the file.
+    ///
+    pub fn path(&self) -> &Path {
+        self.medium.path()
+    }
+}
+
+/// Implements driver trait for the format driver.
+///
+impl<F: Format, M: Medium> Driver for FormatDriver<F, M> {
+
+    /// Reads and decodes the medium's bytes. An empty medium is
+    /// treated as an empty store.
+    ///
+    fn load(&self) -> IOResult {
+        match self.medium.read()? {
+            Some(b) => self.format.decode(&b),
+            None => Ok(Map::new())
+        }
+    }
+
+    /// Encodes all key value pairs and writes them to the medium.
+    ///
+    /// * s: Key value pairs
+    ///
+    fn save(&mut self, s: &Map) -> IOCheck {
+        let b = self.format.encode(s)?;
+        self.medium.write(&b)
+    }
+
+    fn validated(&self) -> bool {
+        self.format.validated()
+    }
+
+    fn lock(&mut self) -> IOCheck {
+        self.medium.lock()
+    }
+
+    fn unlock(&mut self) -> IOCheck {
+        self.medium.unlock()
+    }
+}
+
+/// Implements format trait for the format driver by its format.
+///
+impl<F: Format, M: Medium> Format for FormatDriver<F, M> {
+
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        self.format.encode(s)
+    }
+
+    fn decode(&self, b: &[u8]) -> IOResult {
+        self.format.decode(b)
+    }
+
+    fn validated(&self) -> bool {
+        self.format.validated()
+    }
+}
+
+/// Implements line comments for format drivers whose format has them.
+///
+impl<F: LineComments, M: Medium> LineComments for FormatDriver<F, M> {}
+
+/// Implements byte driver trait for format drivers keeping their
+/// bytes in a file.
+///
+impl<F: Format> ByteDriver for FormatDriver<F, FileMedium> {
+
+    fn path(&self) -> &Path {
+        self.medium.path()
+    }
+}
+
+/// A medium that keeps bytes within a file, replaced atomically on
+/// write. New files are created readable and writable by their owner
+/// only. Reading can also check that no one else may modify the file,
+/// see `PermissionPolicy`.
+///
+pub struct FileMedium {
+    path: PathBuf,
+    lock: LockFile,
+    policy: PermissionPolicy,
+    owner: Option<u32>,
+    mode: u32,
+    warn: Option<WarningHandler>
+}
+
+/// Implements factory methods.
+///
+impl FileMedium {
+
+    /// Creates a new file medium.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> FileMedium {
+        FileMedium {
+            path: path.as_ref().to_path_buf(),
+            lock: LockFile::new(path.as_ref()),
+            policy: PermissionPolicy::Off,
+            owner: None,
+            mode: 0o600,
+            warn: None
+        }
+    }
+
+    /// Sets how the file's permissions and owner are checked on read.
+    /// Off by default.
+    ///
+    /// * p: Permission policy
+    ///
+    pub fn permissions(mut self, p: PermissionPolicy) -> FileMedium {
+        self.policy = p;
+        self
+    }
+
+    /// Sets the user expected to own the file, the effective user of
+    /// the process by default.
+    ///
+    /// * uid: Expected owner
+    ///
+    pub fn owner(mut self, uid: u32) -> FileMedium {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Sets the mode new files are created with, 0600 by default.
+    /// Existing files keep their mode.
+    ///
+    /// * mode: Unix permission bits
+    ///
+    pub fn mode(mut self, mode: u32) -> FileMedium {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets how long `lock` waits for another writer to release the
+    /// file before giving up.
+    ///
+    /// * t: Maximum time to wait
+    ///
+    pub fn lock_timeout(mut self, t: Duration) -> FileMedium {
+        self.lock = self.lock.timeout(t);
+        self
+    }
+
+    /// Sets a function receiving warnings, such as the permission
+    /// problems found under `PermissionPolicy::Warn`. Warnings are
+    /// printed to standard error by default.
+    ///
+    /// * f: Receives each warning
+    ///
+    pub fn warn_with<F>(mut self, f: F) -> FileMedium
+        where F: Fn(&str) + 'static {
+        self.warn = Some(Box::new(f));
+        self
+    }
+
+    /// Get the path of the file.
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Implements medium trait for the file medium.
+///
+impl Medium for FileMedium {
+
+    /// Reads the file, a missing file holds nothing. Permissions are
+    /// checked on the opened file so they describe the bytes read.
+    ///
+    fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut f = match fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+
+        if self.policy != PermissionPolicy::Off {
+            let problems = permission_problems(&self.path, &f.metadata()?, self.owner);
+            if !problems.is_empty() {
+                let msg = problems.join("; ");
+                if self.policy == PermissionPolicy::Deny {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+                }
+                match self.warn {
+                    Some(ref w) => w(&msg),
+                    None => eprintln!("Warning: {}", msg)
+                }
+            }
+        }
+
+        let mut b = Vec::new();
+        f.read_to_end(&mut b)?;
+        Ok(Some(b))
+    }
+
+    fn write(&mut self, b: &[u8]) -> IOCheck {
+        write_atomic_mode(&self.path, b, self.mode)
+    }
+
+    fn lock(&mut self) -> IOCheck {
+        self.lock.acquire()
+    }
+
+    fn unlock(&mut self) -> IOCheck {
+        self.lock.release()
+    }
+}
+
+/// A medium that keeps bytes within memory. Data is lost when this
+/// medium is deallocated.
+///
+#[derive(Default)]
+pub struct MemoryMedium {
+    data: Option<Vec<u8>>
+}
+
+/// Implements factory methods.
+///
+impl MemoryMedium {
+
+    /// Creates a new, empty, memory medium.
+    ///
+    pub fn new() -> MemoryMedium {
+        MemoryMedium { data: None }
+    }
+
+    /// Creates a new memory medium holding some bytes.
+    ///
+    /// * b: Bytes to hold
+    ///
+    pub fn from(b: Vec<u8>) -> MemoryMedium {
+        MemoryMedium { data: Some(b) }
+    }
+
+    /// Get the bytes held.
+    ///
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+}
+
+/// Implements medium trait for the memory medium.
+///
+impl Medium for MemoryMedium {
+
+    fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.data.clone())
+    }
+
+    fn write(&mut self, b: &[u8]) -> IOCheck {
+        self.data = Some(b.to_vec());
+        Ok(())
+    }
+}
+
+/// A medium that reads bytes from standard input and writes them to
+/// standard output, as a filter in a pipeline would. Standard input is
+/// read to its end.
+///
+#[derive(Default)]
+pub struct StdioMedium;
+
+/// Implements medium trait for the stdio medium.
+///
+impl Medium for StdioMedium {
+
+    fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut b = Vec::new();
+        io::stdin().lock().read_to_end(&mut b)?;
+        Ok(Some(b))
+    }
+
+    fn write(&mut self, b: &[u8]) -> IOCheck {
+        let out = io::stdout();
+        let mut out = out.lock();
+        out.write_all(b)?;
+        out.flush()
+    }
+}
+
+/// A medium that exchanges bytes with a peer over a TCP connection.
+/// Each write sends one frame, a u64 little endian length followed by
+/// the bytes, and each read receives one; a frame of length zero holds
+/// nothing.
+///
+pub struct SocketMedium {
+    stream: RefCell<TcpStream>
+}
+
+/// Implements factory methods.
+///
+impl SocketMedium {
+
+    /// Creates a new socket medium.
+    ///
+    /// * stream: Connection to the peer
+    ///
+    pub fn new(stream: TcpStream) -> SocketMedium {
+        SocketMedium { stream: RefCell::new(stream) }
+    }
+}
+
+/// Implements medium trait for the socket medium.
+///
+impl Medium for SocketMedium {
+
+    /// Receives one frame, waiting for the peer to send it.
+    ///
+    fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        read_frame(&mut *self.stream.borrow_mut())
+    }
+
+    /// Sends one frame.
+    ///
+    fn write(&mut self, b: &[u8]) -> IOCheck {
+        write_frame(self.stream.get_mut(), b)
+    }
+}
+
+/// Reads a length prefixed frame.
+///
+/// * r: Source of the frame
+///
+/// Returns: Bytes of the frame within an option, none if it is empty
+///
+pub fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 8];
+    r.read_exact(&mut len)?;
+
+    let len = u64::from_le_bytes(len);
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let mut b = Vec::new();
+    r.take(len).read_to_end(&mut b)?;
+    if (b.len() as u64) < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Frame is truncated"));
+    }
+
+    Ok(Some(b))
+}
+
+/// Writes a length prefixed frame.
+///
+/// * w: Destination of the frame
+/// * b: Bytes of the frame
+///
+pub fn write_frame<W: Write>(w: &mut W, b: &[u8]) -> IOCheck {
+    w.write_all(&(b.len() as u64).to_le_bytes())?;
+    w.write_all(b)?;
+    w.flush()
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use driver::{Driver, JsonDriver, JsonFormat, Map, TextFormat};
+    use driver::test_dir;
+    use super::*;
+    use Store;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        m.insert("a".to_string(), "Rincewind".to_string());
+        m.insert("b".to_string(), "42".to_string());
+        m
+    }
+
+    #[test]
+    fn json_in_memory() {
+        let mut d = FormatDriver::with(JsonFormat::new(), MemoryMedium::new());
+        assert!(d.load().unwrap().is_empty());
+
+        d.save(&make_map()).unwrap();
+        let text = String::from_utf8(d.medium().bytes().unwrap().to_vec()).unwrap();
+        assert!(text.contains("\"a\": \"Rincewind\""), "{}", text);
+        assert_eq!(make_map(), d.load().unwrap());
+    }
+
+    #[test]
+    fn json_in_file_matches_json_driver() {
+        let path = test_dir("medium_file").join("store.json");
+
+        let mut d = FormatDriver::with(JsonFormat::new(), FileMedium::new(&path));
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), JsonDriver::new(&path).load().unwrap());
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(d));
+        s.load().unwrap();
+        s.set("c", "x").unwrap();
+        s.save().unwrap();
+        assert_eq!(3, JsonDriver::new(&path).load().unwrap().len());
+    }
+
+    #[test]
+    fn native_over_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let b = read_frame(&mut stream).unwrap().unwrap();
+            write_frame(&mut stream, &b).unwrap();
+            write_frame(&mut stream, b"").unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut d = FormatDriver::with(TextFormat::new(), SocketMedium::new(stream));
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+        assert!(d.load().unwrap().is_empty());
+
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn truncated_frame_fails() {
+        let mut b: &[u8] = b"\x05\x00\x00\x00\x00\x00\x00\x00abc";
+        assert!(read_frame(&mut b).is_err());
+    }
+}
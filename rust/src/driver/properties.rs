@@ -0,0 +1,336 @@
+//!
+//! # Properties driver
+//!
+//! Reads and writes key value pairs as a Java '.properties' file
+//! following the grammar of 'java.util.Properties':
+//!
+//! - Keys end at the first unescaped '=', ':' or whitespace
+//! - Lines ending in an odd number of '\' continue on the next line
+//! - '\t', '\n', '\r', '\f' and '\uXXXX' escapes, any other escaped
+//!   character stands for itself
+//! - Lines whose first non-blank character is '#' or '!' are comments
+//!
+//! Files are read as UTF-8. On save characters outside printable ASCII
+//! are written as '\uXXXX' escapes so Latin-1 readers agree.
+//!
+//! `PropertiesFormat` encodes properties text wherever the bytes are
+//! kept and a `PropertiesDriver` keeps them in a file.
+//!
+
+use std::io;
+use std::path::Path;
+
+use driver::{Format, IOResult, LineComments, Map};
+use driver::medium::{FileMedium, FormatDriver};
+use Store;
+
+/// The properties format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct PropertiesFormat;
+
+/// A driver that stores key value pairs within a properties file.
+///
+pub type PropertiesDriver = FormatDriver<PropertiesFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl PropertiesFormat {
+
+    /// Creates the properties format.
+    ///
+    pub fn new() -> PropertiesFormat {
+        PropertiesFormat
+    }
+}
+
+/// Implements format trait for the properties format.
+///
+impl Format for PropertiesFormat {
+
+    /// Parses properties text into key value pairs. Keys or values the
+    /// store cannot hold are reported with the line they start on.
+    ///
+    /// * b: Properties text
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let text = String::from_utf8(b.to_vec()).map_err(|e| invalid(e.to_string()))?;
+        let mut m = Map::new();
+
+        for (n, line) in logical_lines(&text) {
+            let (k, v) = split(&line);
+            let k = unescape(k).map_err(|e| invalid(format!("{} at line {}", e, n)))?;
+            let v = unescape(v).map_err(|e| invalid(format!("{} at line {}", e, n)))?;
+
+            if !Store::check_key(&k) {
+                return Err(invalid(format!("Invalid key '{}' at line {}", k, n)));
+            }
+
+            if !Store::check_value(&v) {
+                return Err(invalid(format!("Invalid value for '{}' at line {}", k, n)));
+            }
+
+            m.insert(k, v);
+        }
+
+        Ok(m)
+    }
+
+    /// Formats key value pairs as properties text, sorted by key.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Properties text
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        let mut keys: Vec<&String> = s.keys().collect();
+        keys.sort();
+
+        let mut out = String::new();
+        for k in keys {
+            out.push_str(&escape(k, true));
+            out.push('=');
+            out.push_str(&escape(&s[k], false));
+            out.push('\n');
+        }
+
+        Ok(out.into_bytes())
+    }
+}
+
+/// Implements line comments for the properties format, which also
+/// accepts '!'.
+///
+impl LineComments for PropertiesFormat {}
+
+/// Implements factory methods.
+///
+impl PropertiesDriver {
+
+    /// Creates a new properties driver.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> PropertiesDriver {
+        FormatDriver::with(PropertiesFormat::new(), FileMedium::new(path))
+    }
+}
+
+/// Joins continued lines and drops blanks and comments.
+///
+/// Returns: Each logical line with the number of the line it starts on
+///
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start_matches([' ', '\t', '\x0c']);
+
+        let (n, mut joined) = match current.take() {
+            Some(c) => c,
+            None => {
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                    continue;
+                }
+                (i + 1, String::new())
+            }
+        };
+
+        let slashes = trimmed.chars().rev().take_while(|&c| c == '\\').count();
+        if slashes % 2 == 1 {
+            joined.push_str(&trimmed[..trimmed.len() - 1]);
+            current = Some((n, joined));
+        } else {
+            joined.push_str(trimmed);
+            out.push((n, joined));
+        }
+    }
+
+    if let Some(c) = current {
+        out.push(c);
+    }
+
+    out
+}
+
+/// Splits a logical line into its raw, still escaped, key and value.
+///
+fn split(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    let mut end = line.len();
+
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || c == ' ' || c == '\t' || c == '\x0c' {
+            end = i;
+            break;
+        }
+    }
+
+    let key = &line[..end];
+    let mut rest = line[end..].trim_start_matches([' ', '\t', '\x0c']);
+    if rest.starts_with('=') || rest.starts_with(':') {
+        rest = rest[1..].trim_start_matches([' ', '\t', '\x0c']);
+    }
+
+    (key, rest)
+}
+
+/// Resolves escape sequences. Text is gathered as UTF-16 so that
+/// surrogate pairs written as two '\uXXXX' escapes recombine.
+///
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out: Vec<u16> = Vec::new();
+    let mut chars = s.chars();
+    let mut buf = [0u16; 2];
+
+    while let Some(c) = chars.next() {
+        let c = if c != '\\' {
+            c
+        } else {
+            match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('f') => '\x0c',
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let unit = u16::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 4)
+                        .ok_or_else(|| "Malformed \\uXXXX escape".to_string())?;
+                    out.push(unit);
+                    continue;
+                }
+                Some(c) => c,
+                None => continue
+            }
+        };
+
+        out.extend_from_slice(c.encode_utf16(&mut buf));
+    }
+
+    String::from_utf16(&out).map_err(|_| "Unpaired surrogate in \\uXXXX escape".to_string())
+}
+
+/// Escapes a key or value for writing.
+///
+fn escape(s: &str, key: bool) -> String {
+    let mut out = String::new();
+
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\x0c' => out.push_str("\\f"),
+            ' ' if key || i == 0 => out.push_str("\\ "),
+            '=' | ':' | '#' | '!' if key => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for u in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{:04X}", u));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Format, Map};
+    use driver::test_dir;
+    use super::{PropertiesDriver, PropertiesFormat};
+
+    fn decode(s: &str) -> ::driver::IOResult {
+        PropertiesFormat::new().decode(s.as_bytes())
+    }
+
+    #[test]
+    fn grammar() {
+        let m = decode(concat!(
+            "# comment\n",
+            "   ! also a comment\n",
+            "\n",
+            "equals=1\n",
+            "colon:2\n",
+            "space 3\n",
+            "  padded  =   4  \n",
+            "long = a, \\\n",
+            "       b, \\\n",
+            "       c\n",
+            "unicode = caf\\u00e9 \\uD83D\\uDE00\n",
+            "escaped = \\t\\=\\:\\\\x\n",
+            "empty\n",
+            "odd = ends with \\\\\n",
+            "after = 5\n")).unwrap();
+
+        assert_eq!(10, m.len());
+        assert_eq!("1", m["equals"]);
+        assert_eq!("2", m["colon"]);
+        assert_eq!("3", m["space"]);
+        assert_eq!("4  ", m["padded"]);
+        assert_eq!("a, b, c", m["long"]);
+        assert_eq!("caf\u{e9} \u{1F600}", m["unicode"]);
+        assert_eq!("\t=:\\x", m["escaped"]);
+        assert_eq!("", m["empty"]);
+        assert_eq!("ends with \\", m["odd"]);
+        assert_eq!("5", m["after"]);
+    }
+
+    #[test]
+    fn invalid_keys_report_line() {
+        let e = decode("a=1\n\n# x\ndb.host=x\n").unwrap_err();
+        assert!(e.to_string().contains("'db.host' at line 4"), "{}", e);
+
+        let e = decode("a=1\nb = x\\\n  \\ny\n").unwrap_err();
+        assert!(e.to_string().contains("line 2"), "{}", e);
+
+        let e = decode("a=\\u12\n").unwrap_err();
+        assert!(e.to_string().contains("line 1"), "{}", e);
+    }
+
+    #[test]
+    fn save_load() {
+        let path = test_dir("properties_save").join("store.properties");
+
+        let mut m = Map::new();
+        m.insert("a".to_string(), " leading space".to_string());
+        m.insert("b".to_string(), "caf\u{e9} \u{1F600} \\ \t#!=:".to_string());
+        m.insert("c".to_string(), "".to_string());
+
+        let mut d = PropertiesDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.is_ascii(), "{}", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+}
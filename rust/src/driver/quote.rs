@@ -0,0 +1,149 @@
+//!
+//! # Quoted values
+//!
+//! Reads values of line based formats that may be quoted and followed
+//! by a comment, such as those of dotenv and INI files:
+//!
+//! plain value   # comment
+//! "double quoted with \"escapes\""
+//! 'single quoted, taken literally'
+//!
+//! The formats differ in which characters start a comment and which
+//! escapes double quoted values support, described by a `Dialect`.
+//!
+
+/// How a format quotes values and marks comments.
+///
+pub struct Dialect {
+
+    /// Characters starting a comment.
+    pub comments: &'static [char],
+
+    /// Whether a comment may start an unquoted value, leaving it empty.
+    /// Otherwise a comment must follow whitespace after some text.
+    pub comment_first: bool,
+
+    /// Whether double quoted values support '\n', '\r', '\t' and '\$'
+    /// escapes, keeping any other escape as written. Otherwise only
+    /// '\"' and '\\' are valid.
+    pub escapes: bool
+}
+
+/// Quoting of dotenv files.
+///
+pub const DOTENV: Dialect = Dialect {
+    comments: &['#'],
+    comment_first: true,
+    escapes: true
+};
+
+/// Quoting of INI files.
+///
+pub const INI: Dialect = Dialect {
+    comments: &[';', '#'],
+    comment_first: false,
+    escapes: false
+};
+
+/// Implements reading quoted values.
+///
+impl Dialect {
+
+    /// Checks whether the rest of a line is blank or a comment.
+    ///
+    /// * s: Rest of the line
+    ///
+    pub fn is_comment(&self, s: &str) -> bool {
+        let s = s.trim();
+        s.is_empty() || s.starts_with(self.comments)
+    }
+
+    /// Extracts a value, removing quotes, escapes and trailing
+    /// comments.
+    ///
+    /// * raw: Text after the '='
+    ///
+    /// Returns: Value, or why it is malformed
+    ///
+    pub fn unquote(&self, raw: &str) -> Result<String, String> {
+        let raw = raw.trim();
+        let mut chars = raw.char_indices();
+
+        match chars.next() {
+            Some((_, '"')) => {
+                let mut v = String::new();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '"' if self.is_comment(&raw[i + 1..]) => return Ok(v),
+                        '"' => return Err("Unexpected text after quoted value".to_string()),
+                        '\\' => match chars.next() {
+                            Some((_, e)) if e == '"' || e == '\\' => v.push(e),
+                            Some((_, e)) if self.escapes => match e {
+                                'n' => v.push('\n'),
+                                'r' => v.push('\r'),
+                                't' => v.push('\t'),
+                                '$' => v.push('$'),
+                                e => {
+                                    v.push('\\');
+                                    v.push(e);
+                                }
+                            },
+                            None if self.escapes => return Err("Unclosed quoted value".to_string()),
+                            _ => return Err("Invalid escape in quoted value".to_string())
+                        },
+                        c => v.push(c)
+                    }
+                }
+                Err("Unclosed quoted value".to_string())
+            }
+            Some((_, '\'')) => {
+                match raw[1..].find('\'') {
+                    Some(end) if self.is_comment(&raw[end + 2..]) => Ok(raw[1..end + 1].to_string()),
+                    Some(_) => Err("Unexpected text after quoted value".to_string()),
+                    None => Err("Unclosed quoted value".to_string())
+                }
+            }
+            _ => {
+                let end = raw.char_indices()
+                    .find(|&(i, c)| self.comments.contains(&c) && if i == 0 {
+                        self.comment_first
+                    } else {
+                        raw[..i].ends_with(char::is_whitespace)
+                    })
+                    .map_or(raw.len(), |(i, _)| i);
+                Ok(raw[..end].trim_end().to_string())
+            }
+        }
+    }
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use super::{DOTENV, INI};
+
+    #[test]
+    fn dialects_differ() {
+        assert_eq!(Ok("a\nb".to_string()), DOTENV.unquote("\"a\\nb\""));
+        assert!(INI.unquote("\"a\\nb\"").is_err());
+
+        assert_eq!(Ok("x ; y".to_string()), DOTENV.unquote("x ; y"));
+        assert_eq!(Ok("x".to_string()), INI.unquote("x ; y"));
+
+        assert_eq!(Ok("".to_string()), DOTENV.unquote("#x"));
+        assert_eq!(Ok("#x".to_string()), INI.unquote("#x"));
+
+        for d in &[DOTENV, INI] {
+            assert_eq!(Ok("a # b".to_string()), d.unquote("'a # b' # c"));
+            assert_eq!(Ok("q\"".to_string()), d.unquote("\"q\\\"\""));
+            assert!(d.unquote("'a' b").is_err());
+            assert!(d.unquote("\"a").is_err());
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//!
+//! # Signed driver
+//!
+//! Wraps a format driver whose format treats lines starting with '#'
+//! as comments, marked by `LineComments`, such as the file, dotenv,
+//! INI, properties and TOML drivers, so a trailer line guarding the
+//! rest of the bytes is written on save and verified on load:
+//!
+//! #checksum sha256:<hex digest>
+//! #hmac sha256:<hex digest>
+//!
+//! A checksum detects corruption while an HMAC, keyed by a secret,
+//! also detects tampering. A trailer of the other kind is never
+//! trusted, so an HMAC cannot be swapped for a checksum. Readers that
+//! do not know the trailer see an ordinary comment.
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use driver::{Format, IOResult, LineComments, Map};
+use driver::medium::{FormatDriver, Medium};
+
+/// Starts a checksum trailer.
+///
+pub const CHECKSUM_PREFIX: &str = "#checksum sha256:";
+
+/// Starts an HMAC trailer.
+///
+pub const HMAC_PREFIX: &str = "#hmac sha256:";
+
+/// How files are guarded.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub enum Integrity {
+
+    /// A SHA-256 digest detecting corruption.
+    Checksum,
+
+    /// A HMAC-SHA-256 keyed with a secret detecting tampering.
+    Hmac(Vec<u8>)
+}
+
+/// Implements debugging integrity without revealing HMAC keys.
+///
+impl fmt::Debug for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Integrity::Checksum => write!(f, "Checksum"),
+            Integrity::Hmac(_) => write!(f, "Hmac(..)")
+        }
+    }
+}
+
+/// Errors specific to integrity checks, carried within an `io::Error`
+/// of kind `InvalidData`. Use `IntegrityError::of` to find one.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+
+    /// The trailer does not match the content.
+    Mismatch,
+
+    /// The file has no trailer the driver can verify and unsigned
+    /// files are refused.
+    Unsigned
+}
+
+/// Implements integrity error methods.
+///
+impl IntegrityError {
+
+    /// Finds the integrity error within an IO error.
+    ///
+    /// * e: IO error returned by the crate
+    ///
+    /// Returns: Integrity error within an option
+    ///
+    pub fn of(e: &io::Error) -> Option<&IntegrityError> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<IntegrityError>())
+    }
+}
+
+/// Implements displaying integrity errors.
+///
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntegrityError::Mismatch =>
+                write!(f, "Integrity check failed: file was corrupted or tampered with"),
+            IntegrityError::Unsigned =>
+                write!(f, "Integrity check failed: file is not signed")
+        }
+    }
+}
+
+impl error::Error for IntegrityError {}
+
+/// Implements wrapping integrity errors as IO errors.
+///
+impl From<IntegrityError> for io::Error {
+    fn from(e: IntegrityError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A format that guards the bytes of another format with a trailer.
+///
+pub struct SignedFormat<F: LineComments> {
+    inner: F,
+    integrity: Integrity,
+    require: bool
+}
+
+/// A driver that guards the bytes another format driver keeps within
+/// its medium with a trailer.
+///
+pub type SignedDriver<F, M> = FormatDriver<SignedFormat<F>, M>;
+
+/// Implements factory methods.
+///
+impl<F: LineComments> SignedFormat<F> {
+
+    /// Creates a new signed format. Unsigned bytes are still decoded.
+    ///
+    /// * inner: Format whose bytes are guarded
+    /// * integrity: How the bytes are guarded
+    ///
+    pub fn new(inner: F, integrity: Integrity) -> SignedFormat<F> {
+        SignedFormat {
+            inner,
+            integrity,
+            require: false
+        }
+    }
+
+    /// Sets whether bytes without a trailer the format can verify are
+    /// refused.
+    ///
+    /// * r: True to refuse unsigned bytes
+    ///
+    pub fn require(mut self, r: bool) -> SignedFormat<F> {
+        self.require = r;
+        self
+    }
+
+    /// Get the wrapped format.
+    ///
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+}
+
+/// Implements format trait for the signed format.
+///
+impl<F: LineComments> Format for SignedFormat<F> {
+
+    /// Encodes key value pairs with the wrapped format and appends the
+    /// trailer.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: Content followed by the trailer
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        Ok(sign(&self.inner.encode(s)?, &self.integrity))
+    }
+
+    /// Verifies and removes the trailer then decodes the content with
+    /// the wrapped format.
+    ///
+    /// * b: Content that may end in a trailer
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        self.inner.decode(verify(b, &self.integrity, self.require)?)
+    }
+
+    fn validated(&self) -> bool {
+        self.inner.validated()
+    }
+}
+
+/// Implements factory methods.
+///
+impl<F: LineComments, M: Medium> SignedDriver<F, M> {
+
+    /// Creates a new signed driver writing to the medium of another
+    /// driver. Unsigned files are still loaded.
+    ///
+    /// * inner: Driver whose bytes are guarded
+    /// * integrity: How the bytes are guarded
+    ///
+    pub fn new(inner: FormatDriver<F, M>, integrity: Integrity) -> SignedDriver<F, M> {
+        let (format, medium) = inner.into_parts();
+        FormatDriver::with(SignedFormat::new(format, integrity), medium)
+    }
+
+    /// Sets whether files without a trailer the driver can verify are
+    /// refused.
+    ///
+    /// * r: True to refuse unsigned files
+    ///
+    pub fn require(mut self, r: bool) -> SignedDriver<F, M> {
+        self.format_mut().require = r;
+        self
+    }
+}
+
+/// Appends a trailer line to some content.
+///
+/// * b: Content to guard
+/// * i: How to guard it
+///
+/// Returns: Content followed by the trailer
+///
+pub fn sign(b: &[u8], i: &Integrity) -> Vec<u8> {
+    let mut out = b.to_vec();
+    if !out.is_empty() && !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+
+    let trailer = match *i {
+        Integrity::Checksum => format!("{}{}\n", CHECKSUM_PREFIX, hex(&checksum(&out))),
+        Integrity::Hmac(ref key) => format!("{}{}\n", HMAC_PREFIX, hex(&hmac(key, &out)))
+    };
+
+    out.extend_from_slice(trailer.as_bytes());
+    out
+}
+
+/// Verifies and removes a trailer line.
+///
+/// * b: Content that may end in a trailer
+/// * i: How the content is guarded
+/// * require: Whether content without a trailer is refused. A trailer
+///   of the other kind is refused regardless.
+///
+/// Returns: Content without the trailer
+///
+pub fn verify<'a>(b: &'a [u8], i: &Integrity, require: bool) -> io::Result<&'a [u8]> {
+    let body_len = match b[..b.len().saturating_sub(1)].iter().rposition(|&c| c == b'\n') {
+        Some(n) => n + 1,
+        None => 0
+    };
+    let (body, last) = b.split_at(body_len);
+
+    let last = String::from_utf8_lossy(last);
+    let last = last.trim_end();
+
+    let expected = match *i {
+        Integrity::Checksum if last.starts_with(CHECKSUM_PREFIX) => {
+            Some((&last[CHECKSUM_PREFIX.len()..], checksum(body)))
+        }
+        Integrity::Hmac(ref key) if last.starts_with(HMAC_PREFIX) => {
+            Some((&last[HMAC_PREFIX.len()..], hmac(key, body)))
+        }
+        _ => None
+    };
+
+    match expected {
+        Some((found, digest)) if same(&found.to_ascii_lowercase(), &hex(&digest)) => Ok(body),
+        Some(_) => Err(IntegrityError::Mismatch.into()),
+        None if require => Err(IntegrityError::Unsigned.into()),
+        None if last.starts_with(CHECKSUM_PREFIX) || last.starts_with(HMAC_PREFIX) => {
+            Err(IntegrityError::Unsigned.into())
+        }
+        None => Ok(b)
+    }
+}
+
+/// Computes the SHA-256 digest of some bytes.
+///
+fn checksum(b: &[u8]) -> Vec<u8> {
+    Sha256::digest(b).to_vec()
+}
+
+/// Computes the HMAC-SHA-256 of some bytes.
+///
+fn hmac(key: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut m = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .expect("HMAC accepts keys of any length");
+    m.update(b);
+    m.finalize().into_bytes().to_vec()
+}
+
+/// Compares digests in constant time so an HMAC cannot be guessed a
+/// digit at a time.
+///
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+/// Writes bytes as lower case hex digits.
+///
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, DotenvDriver, FileDriver, Map};
+    use driver::test_dir;
+    use super::*;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        m.insert("a".to_string(), "1".to_string());
+        m.insert("b".to_string(), "Rincewind".to_string());
+        m
+    }
+
+    #[test]
+    fn checksum_trailer() {
+        let path = test_dir("signed_checksum").join("store.txt");
+
+        let mut d = SignedDriver::new(FileDriver::new(&path), Integrity::Checksum);
+        d.save(&make_map()).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("a=1\nb=Rincewind\n#checksum sha256:"), "{}", text);
+        assert_eq!(make_map(), d.load().unwrap());
+
+        // Plain readers see the trailer as a comment
+        assert_eq!(make_map(), FileDriver::new(&path).load().unwrap());
+
+        fs::write(&path, text.replace("a=1", "a=2")).unwrap();
+        let e = d.load().unwrap_err();
+        assert_eq!(Some(&IntegrityError::Mismatch), IntegrityError::of(&e));
+    }
+
+    #[test]
+    fn hmac_trailer() {
+        let path = test_dir("signed_hmac").join(".env");
+        let key = b"not so secret".to_vec();
+
+        let mut d = SignedDriver::new(DotenvDriver::new(&path), Integrity::Hmac(key.clone()));
+        d.save(&make_map()).unwrap();
+        assert_eq!(make_map(), d.load().unwrap());
+
+        let d = SignedDriver::new(DotenvDriver::new(&path), Integrity::Hmac(b"other".to_vec()));
+        let e = d.load().unwrap_err();
+        assert_eq!(Some(&IntegrityError::Mismatch), IntegrityError::of(&e));
+
+        // A checksum cannot stand in for an HMAC, required or not
+        SignedDriver::new(DotenvDriver::new(&path), Integrity::Checksum).save(&make_map()).unwrap();
+        for &r in &[false, true] {
+            let i = Integrity::Hmac(key.clone());
+            let d = SignedDriver::new(DotenvDriver::new(&path), i).require(r);
+            let e = d.load().unwrap_err();
+            assert_eq!(Some(&IntegrityError::Unsigned), IntegrityError::of(&e));
+        }
+
+        // Nor can a tampered file whose trailer was swapped for one
+        let text = fs::read_to_string(&path).unwrap();
+        let body = &text[..text.find("#checksum").unwrap()];
+        fs::write(&path, format!("{}A=evil\n#checksum sha256:00\n", body)).unwrap();
+        let d = SignedDriver::new(DotenvDriver::new(&path), Integrity::Hmac(key));
+        let e = d.load().unwrap_err();
+        assert_eq!(Some(&IntegrityError::Unsigned), IntegrityError::of(&e));
+    }
+
+    #[test]
+    fn unsigned_files() {
+        let path = test_dir("signed_unsigned").join("store.txt");
+        FileDriver::new(&path).save(&make_map()).unwrap();
+
+        let d = SignedDriver::new(FileDriver::new(&path), Integrity::Checksum);
+        assert_eq!(make_map(), d.load().unwrap());
+
+        let d = d.require(true);
+        let e = d.load().unwrap_err();
+        assert_eq!(Some(&IntegrityError::Unsigned), IntegrityError::of(&e));
+        assert!(e.to_string().contains("not signed"), "{}", e);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_file_settings() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = test_dir("signed_mode").join("store.txt");
+        let mut d = SignedDriver::new(FileDriver::new(&path).mode(0o640), Integrity::Checksum);
+        d.save(&make_map()).unwrap();
+        assert_eq!(0o640, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn empty_content() {
+        let signed = sign(b"", &Integrity::Checksum);
+        assert_eq!(b"", verify(&signed, &Integrity::Checksum, true).unwrap());
+        assert_eq!(b"x=1", verify(b"x=1", &Integrity::Checksum, false).unwrap());
+    }
+}
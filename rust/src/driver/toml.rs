@@ -0,0 +1,284 @@
+//!
+//! # TOML driver
+//!
+//! Reads and writes key value pairs as a TOML document. Tables are
+//! flattened into keys joined with '_' so
+//!
+//! [db]
+//! host = "x"
+//!
+//! becomes 'db_host=x'. Arrays of scalars become lists in the default
+//! `ListFormat`, so an element holding a ',' is escaped and `get_vec`
+//! reads it back whole. On save keys are regrouped into tables by the
+//! text before their first '_' and every value is written as a string.
+//!
+//! `TomlFormat` encodes TOML wherever the bytes are kept and a
+//! `TomlDriver` keeps them in a file.
+//!
+
+use std::io;
+use std::path::Path;
+
+use toml;
+use toml::{Table, Value};
+
+use driver::{Format, IOCheck, IOResult, LineComments, Map};
+use driver::medium::{FileMedium, FormatDriver};
+use list::ListFormat;
+use Store;
+
+/// The TOML format.
+///
+#[derive(Debug, Clone, Default)]
+pub struct TomlFormat;
+
+/// A driver that stores key value pairs within a TOML file.
+///
+pub type TomlDriver = FormatDriver<TomlFormat, FileMedium>;
+
+/// Implements factory methods.
+///
+impl TomlFormat {
+
+    /// Creates the TOML format.
+    ///
+    pub fn new() -> TomlFormat {
+        TomlFormat
+    }
+}
+
+/// Implements format trait for the TOML format.
+///
+impl Format for TomlFormat {
+
+    /// Parses a TOML document into flattened key value pairs.
+    ///
+    /// * b: TOML text
+    ///
+    /// Returns: Map of key value pairs
+    ///
+    fn decode(&self, b: &[u8]) -> IOResult {
+        let text = String::from_utf8(b.to_vec()).map_err(|e| invalid(e.to_string()))?;
+        let t: Table = text.parse().map_err(|e| invalid(format!("Invalid TOML: {}", e)))?;
+
+        let mut m = Map::new();
+        flatten(&mut m, "", &t)?;
+        Ok(m)
+    }
+
+    /// Formats key value pairs as a TOML document, grouping keys into
+    /// tables by the text before their first '_'.
+    ///
+    /// * s: Key value pairs
+    ///
+    /// Returns: TOML text
+    ///
+    fn encode(&self, s: &Map) -> io::Result<Vec<u8>> {
+        let mut t = Table::new();
+
+        for (k, v) in s.iter() {
+            let v = Value::String(v.clone());
+
+            match split(k) {
+                Some((table, child)) if !s.contains_key(table) => {
+                    t.entry(table)
+                        .or_insert_with(|| Value::Table(Table::new()))
+                        .as_table_mut()
+                        .unwrap()
+                        .insert(child.to_string(), v);
+                }
+                _ => {
+                    t.insert(k.clone(), v);
+                }
+            }
+        }
+
+        let text = toml::to_string(&t).map_err(|e| invalid(e.to_string()))?;
+        Ok(text.into_bytes())
+    }
+}
+
+/// Implements line comments for the TOML format.
+///
+impl LineComments for TomlFormat {}
+
+/// Implements factory methods.
+///
+impl TomlDriver {
+
+    /// Creates a new TOML driver.
+    ///
+    /// * path: Path of the file to read and write
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> TomlDriver {
+        FormatDriver::with(TomlFormat::new(), FileMedium::new(path))
+    }
+}
+
+/// Adds the entries of a table to a map, prefixing their keys.
+///
+fn flatten(m: &mut Map, prefix: &str, t: &Table) -> IOCheck {
+    for (k, v) in t.iter() {
+        let key = format!("{}{}", prefix, k);
+
+        let s = match *v {
+            Value::Table(ref t) => {
+                flatten(m, &format!("{}_", key), t)?;
+                continue;
+            }
+            Value::Array(ref a) => {
+                let mut items = Vec::new();
+                for item in a {
+                    items.push(scalar(&key, item)?);
+                }
+                ListFormat::new().join(&items)
+            }
+            ref v => scalar(&key, v)?
+        };
+
+        if !Store::check_key(&key) {
+            return Err(invalid(format!("Invalid key '{}'", key)));
+        }
+
+        if !Store::check_value(&s) {
+            return Err(invalid(format!("Invalid value for '{}'", key)));
+        }
+
+        if m.insert(key.clone(), s).is_some() {
+            return Err(invalid(format!("Duplicate key '{}' after flattening", key)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a scalar TOML value to a string.
+///
+fn scalar(key: &str, v: &Value) -> io::Result<String> {
+    match *v {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Datetime(ref d) => Ok(d.to_string()),
+        Value::Array(_) => Err(invalid(format!(
+            "Nested array in '{}' cannot be represented as a value", key))),
+        Value::Table(_) => Err(invalid(format!(
+            "Table in array '{}' cannot be represented as a value", key)))
+    }
+}
+
+/// Splits a key into a table name and child key at its first '_'.
+///
+fn split(k: &str) -> Option<(&str, &str)> {
+    match k.find('_') {
+        Some(i) if i > 0 && i + 1 < k.len() => Some((&k[..i], &k[i + 1..])),
+        _ => None
+    }
+}
+
+/// Creates an invalid data error.
+///
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::{Driver, Format, Map};
+    use driver::test_dir;
+    use super::{TomlDriver, TomlFormat};
+    use Store;
+
+    fn decode(s: &str) -> ::driver::IOResult {
+        TomlFormat::new().decode(s.as_bytes())
+    }
+
+    #[test]
+    fn tables_flatten() {
+        let m = decode(r#"
+            name = "app"
+            hosts = ["a", "b", "c"]
+
+            [db]
+            host = "x"
+            port = 5432
+            ratio = 0.5
+            tls = true
+
+            [db.pool]
+            size = 4
+        "#).unwrap();
+
+        assert_eq!(7, m.len());
+        assert_eq!("app", m["name"]);
+        assert_eq!("a,b,c", m["hosts"]);
+        assert_eq!("x", m["db_host"]);
+        assert_eq!("4", m["db_pool_size"]);
+
+        let s = Store::from(m);
+        assert_eq!(5432, s.get_u16("db_port").unwrap().unwrap());
+        assert_eq!(0.5, s.get_f64("db_ratio").unwrap().unwrap());
+        assert!(s.get_bool("db_tls").unwrap().unwrap());
+    }
+
+    #[test]
+    fn unrepresentable_values_fail() {
+        for toml in &["a = [[1], [2]]", "[[a]]\nb = 1", "a = \"x\\ny\"",
+                      "a-b = 1", "a_b = 1\n[a]\nb = 2", "a = "] {
+            assert!(decode(toml).is_err(), "{} should fail", toml);
+        }
+    }
+
+    #[test]
+    fn save_unflattens() {
+        let path = test_dir("toml_save").join("store.toml");
+
+        let mut m = Map::new();
+        m.insert("name".to_string(), "app".to_string());
+        m.insert("db_host".to_string(), "x".to_string());
+        m.insert("db_pool_size".to_string(), "4".to_string());
+        m.insert("_private".to_string(), "1".to_string());
+
+        let mut d = TomlDriver::new(&path);
+        d.save(&m).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("[db]\nhost = \"x\"\npool_size = \"4\"\n"), "{}", text);
+        assert_eq!(m, d.load().unwrap());
+    }
+
+    #[test]
+    fn arrays_round_trip() {
+        let path = test_dir("toml_arrays").join("store.toml");
+        fs::write(&path, "hosts = [\"a,b\", \"c\", \" d\"]\n").unwrap();
+
+        let mut d = TomlDriver::new(&path);
+        let m = d.load().unwrap();
+        assert_eq!("a\\,b,c,\\ d", m["hosts"]);
+
+        d.save(&m).unwrap();
+        let s = Store::from(d.load().unwrap());
+        assert_eq!(vec!["a,b", "c", " d"], s.get_vec::<String>("hosts").unwrap().unwrap());
+    }
+
+    #[test]
+    fn store_uses_driver() {
+        let path = test_dir("toml_store").join("store.toml");
+        fs::write(&path, "[db]\nhost = \"x\"\n").unwrap();
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(TomlDriver::new(&path)));
+        s.load().unwrap();
+        assert_eq!(Some(&"x".to_string()), s.get("db_host"));
+    }
+}
@@ -0,0 +1,418 @@
+//!
+//! # Typed getters
+//!
+//! Getters that parse string values into other types. They are shared
+//! by every store type that provides a `get` method returning a string
+//! for a key and a `lenient` method telling whether parsing is lenient.
+//!
+//! Lenient parsing, which is off by default, also reads integers with
+//! the radix prefixes '0x', '0o' and '0b' or with '_' between digits,
+//! e.g. '0x1F' or '1_000_000', and bools written as yes/no, on/off or
+//! 1/0 in any case, including as the elements of lists.
+//!
+
+use std::num::ParseIntError;
+use std::str::{FromStr, ParseBoolError};
+
+/// Integers that can be parsed in a radix.
+///
+pub trait Radix: FromStr<Err = ParseIntError> {
+
+    /// Parses an integer in a radix, as `from_str_radix`.
+    ///
+    fn parse_radix(s: &str, radix: u32) -> Result<Self, ParseIntError>;
+}
+
+macro_rules! radix_impls {
+    ($($t:ty)*) => {
+        $(
+            impl Radix for $t {
+                fn parse_radix(s: &str, radix: u32) -> Result<$t, ParseIntError> {
+                    <$t>::from_str_radix(s, radix)
+                }
+            }
+        )*
+    }
+}
+
+radix_impls!(i8 u8 i16 u16 i32 u32 i64 u64 isize usize);
+
+/// Parses an integer.
+///
+/// * v: Value to parse
+/// * lenient: Whether radix prefixes and underscores are accepted
+///
+/// Returns: Integer, or the error of the standard parser
+///
+pub fn parse_int<T: Radix>(v: &str, lenient: bool) -> Result<T, ParseIntError> {
+    if !lenient {
+        return v.parse::<T>();
+    }
+
+    let (sign, rest) = match v.as_bytes().first() {
+        Some(&b'-') => ("-", &v[1..]),
+        Some(&b'+') => ("+", &v[1..]),
+        _ => ("", v)
+    };
+
+    let (radix, digits) = match rest.get(..2).map(|p| p.to_ascii_lowercase()) {
+        Some(ref p) if p == "0x" => (16, &rest[2..]),
+        Some(ref p) if p == "0o" => (8, &rest[2..]),
+        Some(ref p) if p == "0b" => (2, &rest[2..]),
+        _ => (10, rest)
+    };
+
+    // A sign after the prefix is kept behind another so the standard
+    // parser refuses it
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    let sign = if digits.starts_with(['+', '-']) { "+" } else { sign };
+    T::parse_radix(&format!("{}{}", sign, digits), radix)
+}
+
+/// Parses a bool.
+///
+/// * v: Value to parse
+/// * lenient: Whether yes/no, on/off and 1/0 are accepted
+///
+/// Returns: Bool, or the error of the standard parser
+///
+pub fn parse_bool(v: &str, lenient: bool) -> Result<bool, ParseBoolError> {
+    if lenient {
+        match &*v.to_ascii_lowercase() {
+            "true" | "yes" | "on" | "1" => return Ok(true),
+            "false" | "no" | "off" | "0" => return Ok(false),
+            _ => ()
+        }
+    }
+
+    v.parse::<bool>()
+}
+
+/// Expands to the typed getters within an `impl` block of a type with a
+/// `get(&self, k) -> Option<S>` method where `S` derefs to `str` and a
+/// `lenient(&self) -> bool` method.
+///
+macro_rules! typed_getters {
+    () => {
+        /// Gets a value as a char within an option.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_char(&self, k: &str) -> Option<char> {
+            self.get(k).and_then(|v| v.chars().next())
+        }
+
+        /// Gets a value as an i8 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_i8(&self, k: &str) -> $crate::IntResult<i8> {
+            self.get(k).map(|v| $crate::getters::parse_int::<i8>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an u8 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_u8(&self, k: &str) -> $crate::IntResult<u8> {
+            self.get(k).map(|v| $crate::getters::parse_int::<u8>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an i16 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_i16(&self, k: &str) -> $crate::IntResult<i16> {
+            self.get(k).map(|v| $crate::getters::parse_int::<i16>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an u16 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_u16(&self, k: &str) -> $crate::IntResult<u16> {
+            self.get(k).map(|v| $crate::getters::parse_int::<u16>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an i32 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_i32(&self, k: &str) -> $crate::IntResult<i32> {
+            self.get(k).map(|v| $crate::getters::parse_int::<i32>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an u32 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_u32(&self, k: &str) -> $crate::IntResult<u32> {
+            self.get(k).map(|v| $crate::getters::parse_int::<u32>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an i64 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_i64(&self, k: &str) -> $crate::IntResult<i64> {
+            self.get(k).map(|v| $crate::getters::parse_int::<i64>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an u64 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_u64(&self, k: &str) -> $crate::IntResult<u64> {
+            self.get(k).map(|v| $crate::getters::parse_int::<u64>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an f32 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_f32(&self, k: &str) -> $crate::FloatResult<f32> {
+            self.get(k).map(|v| v.parse::<f32>())
+        }
+
+        /// Gets a value as an f64 within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_f64(&self, k: &str) -> $crate::FloatResult<f64> {
+            self.get(k).map(|v| v.parse::<f64>())
+        }
+
+        /// Gets a value as an isize within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_isize(&self, k: &str) -> $crate::IntResult<isize> {
+            self.get(k).map(|v| $crate::getters::parse_int::<isize>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an usize within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_usize(&self, k: &str) -> $crate::IntResult<usize> {
+            self.get(k).map(|v| $crate::getters::parse_int::<usize>(&v, self.lenient()))
+        }
+
+        /// Gets a value as an bool within a number result.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_bool(&self, k: &str) -> $crate::BoolResult {
+            self.get(k).map(|v| $crate::getters::parse_bool(&v, self.lenient()))
+        }
+
+        /// Gets a value as a list of elements using the default list
+        /// format. Integer and bool elements are parsed leniently when
+        /// the getters are.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Elements as a result naming the failed element.
+        ///
+        #[allow(dead_code)]
+        pub fn get_vec<T: $crate::list::Element>(&self, k: &str) -> $crate::list::VecResult<T> {
+            self.get_vec_with(k, &$crate::list::ListFormat::new())
+        }
+
+        /// Gets a value as a list of elements using a list format,
+        /// parsing elements as `get_vec` does.
+        ///
+        /// * k: Key of the value.
+        /// * f: How the list is written.
+        ///
+        /// Returns: Elements as a result naming the failed element.
+        ///
+        #[allow(dead_code)]
+        pub fn get_vec_with<T: $crate::list::Element>(&self, k: &str, f: &$crate::list::ListFormat)
+            -> $crate::list::VecResult<T> {
+            self.get(k).map(|v| f.parse_lenient::<T>(&v, self.lenient()))
+        }
+
+        /// Gets a value as a duration such as '250ms', '30s' or '1h30m'.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_duration(&self, k: &str) -> $crate::units::DurationResult {
+            self.get(k).map(|v| $crate::units::parse_duration(&v))
+        }
+
+        /// Gets a value as a number of bytes such as '512', '10KB' or
+        /// '2GiB'.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_byte_size(&self, k: &str) -> $crate::units::ByteSizeResult {
+            self.get(k).map(|v| $crate::units::parse_byte_size(&v))
+        }
+
+        /// Gets a value as an IP address, either IPv4 or IPv6.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_ip(&self, k: &str) -> $crate::AddrResult<::std::net::IpAddr> {
+            self.get(k).map(|v| v.parse::<::std::net::IpAddr>())
+        }
+
+        /// Gets a value as a socket address such as '127.0.0.1:8080' or
+        /// '[::1]:8080'.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_socket_addr(&self, k: &str) -> $crate::AddrResult<::std::net::SocketAddr> {
+            self.get(k).map(|v| v.parse::<::std::net::SocketAddr>())
+        }
+
+        /// Gets a value as a path, expanding a leading '~' and any
+        /// '$HOME' or '${HOME}' to the home directory.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[allow(dead_code)]
+        pub fn get_path(&self, k: &str) -> $crate::path::PathResult {
+            self.get(k).map(|v| $crate::path::expand(&v))
+        }
+
+        /// Gets a value as an RFC 3339 date and time such as
+        /// '2024-05-01T12:30:00+02:00', keeping its offset.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[cfg(feature = "chrono")]
+        #[allow(dead_code)]
+        pub fn get_datetime(&self, k: &str) -> $crate::DateTimeResult {
+            self.get(k).map(|v| $crate::chrono::DateTime::parse_from_rfc3339(&v))
+        }
+
+        /// Gets a value as a date written as 'YYYY-MM-DD'.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[cfg(feature = "chrono")]
+        #[allow(dead_code)]
+        pub fn get_date(&self, k: &str) -> $crate::DateResult {
+            self.get(k).map(|v| $crate::chrono::NaiveDate::parse_from_str(&v, "%Y-%m-%d"))
+        }
+
+        /// Gets a value as a URL.
+        ///
+        /// * k: Key of the value.
+        ///
+        /// Returns: Value as a result.
+        ///
+        #[cfg(feature = "url")]
+        #[allow(dead_code)]
+        pub fn get_url(&self, k: &str) -> $crate::UrlResult {
+            self.get(k).map(|v| $crate::url::Url::parse(&v))
+        }
+    }
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn lenient_ints() {
+        assert_eq!(Ok(31), parse_int::<u8>("0x1F", true));
+        assert_eq!(Ok(0o755), parse_int::<u16>("0o755", true));
+        assert_eq!(Ok(10), parse_int::<i32>("0B1010", true));
+        assert_eq!(Ok(1_000_000), parse_int::<u64>("1_000_000", true));
+        assert_eq!(Ok(-255), parse_int::<i16>("-0xff", true));
+        assert_eq!(Ok(42), parse_int::<usize>("+42", true));
+
+        assert!(parse_int::<u8>("0x100", true).is_err());
+        assert!(parse_int::<u8>("-0x1", true).is_err());
+        assert!(parse_int::<i8>("0x--1", true).is_err());
+        assert!(parse_int::<i8>("0x+1", true).is_err());
+        assert!(parse_int::<i8>("0x", true).is_err());
+        assert!(parse_int::<i8>("0b102", true).is_err());
+
+        assert!(parse_int::<u8>("0x1F", false).is_err());
+        assert!(parse_int::<u32>("1_000", false).is_err());
+    }
+
+    #[test]
+    fn lenient_bools() {
+        for v in &["true", "YES", "On", "1"] {
+            assert_eq!(Ok(true), parse_bool(v, true), "{}", v);
+        }
+        for v in &["false", "no", "OFF", "0"] {
+            assert_eq!(Ok(false), parse_bool(v, true), "{}", v);
+        }
+        assert!(parse_bool("maybe", true).is_err());
+        assert!(parse_bool("yes", false).is_err());
+        assert!(parse_bool("True", false).is_err());
+    }
+}
@@ -8,17 +8,80 @@
 //! r"^([_a-zA-Z][_a-zA-Z0-9]+)=([^\n]*)$"
 //!
 
+#[cfg(feature = "encryption")]
+extern crate base64;
+#[cfg(feature = "encryption")]
+extern crate chacha20poly1305;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+extern crate hmac;
+#[cfg(unix)]
+extern crate libc;
+extern crate memmap2;
 extern crate regex;
+extern crate serde_json;
+extern crate sha2;
+extern crate toml;
+#[cfg(feature = "url")]
+extern crate url;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
-pub mod driver;
+#[macro_use]
+mod getters;
 
+pub mod driver;
+pub mod list;
+pub mod mapped;
+pub mod path;
+pub mod redact;
+pub mod units;
+#[cfg(feature = "encryption")]
+mod secret;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use driver::Change;
 use driver::Driver;
 use driver::MemoryDriver;
+use list::ListFormat;
+use redact::{Redact, REDACTED};
+#[cfg(feature = "encryption")]
+use driver::encrypted::{is_sealed, seal, Key};
 
 type IntResult<T> = Option<Result<T, std::num::ParseIntError>>;
 type FloatResult<T> = Option<Result<T, std::num::ParseFloatError>>;
 type BoolResult = Option<Result<bool, std::str::ParseBoolError>>;
+type AddrResult<T> = Option<Result<T, std::net::AddrParseError>>;
+#[cfg(feature = "chrono")]
+type DateTimeResult = Option<Result<chrono::DateTime<chrono::FixedOffset>, chrono::ParseError>>;
+#[cfg(feature = "chrono")]
+type DateResult = Option<Result<chrono::NaiveDate, chrono::ParseError>>;
+#[cfg(feature = "url")]
+type UrlResult = Option<Result<url::Url, url::ParseError>>;
+
+/// Result of a conditional update: whether the new value was written
+/// and the value observed in the store at the time of the check.
+///
+pub type SwapResult = io::Result<(bool, Option<String>)>;
+
+/// Expiry times of keys in milliseconds since the Unix epoch.
+///
+type Expiry = HashMap<String, u64>;
+
+/// Prefix of the companion keys that carry expiry times through
+/// drivers, e.g. the expiry of 'token' is saved as '__expires_token'.
+/// Readers that only understand the plain format see them as ordinary
+/// key value pairs.
+///
+pub const EXPIRY_PREFIX: &str = "__expires_";
 
 /// Represents a key value store for accessing and storing key value
 /// pairs as well as providing functionality to read and write those
@@ -26,7 +89,15 @@ type BoolResult = Option<Result<bool, std::str::ParseBoolError>>;
 ///
 pub struct Store {
     data: driver::Map,
-    driver: Box<Driver>
+    expiry: Expiry,
+    timed: HashSet<String>,
+    changes: driver::Changes,
+    synced: bool,
+    driver: Box<dyn Driver>,
+    redact: Redact,
+    lenient: bool,
+    #[cfg(feature = "encryption")]
+    secrets: secret::Secrets
 }
 
 /// Implements public store methods.
@@ -39,19 +110,40 @@ impl Store {
     pub fn new() -> Store {
         Store {
             data: driver::Map::new(),
-            driver: Box::new(MemoryDriver::new())
+            expiry: Expiry::new(),
+            timed: HashSet::new(),
+            changes: driver::Changes::new(),
+            synced: true,
+            driver: Box::new(MemoryDriver::new()),
+            redact: Redact::new(),
+            lenient: false,
+            #[cfg(feature = "encryption")]
+            secrets: secret::Secrets::new()
         }
     }
 
-    /// Creates a new store initialised with some key value pairs.
+    /// Creates a new store initialised with some key value pairs. The
+    /// pairs count as added until saved.
     ///
     /// * m: Key value pairs to initialise with.
     ///
     #[allow(dead_code)]
     pub fn from(m: driver::Map) -> Store {
+        let changes = m.keys()
+            .map(|k| (k.clone(), Change::Added))
+            .collect();
+
         Store {
             data: m,
-            driver: Box::new(MemoryDriver::new())
+            expiry: Expiry::new(),
+            timed: HashSet::new(),
+            changes,
+            synced: true,
+            driver: Box::new(MemoryDriver::new()),
+            redact: Redact::new(),
+            lenient: false,
+            #[cfg(feature = "encryption")]
+            secrets: secret::Secrets::new()
         }
     }
 
@@ -62,7 +154,7 @@ impl Store {
     /// Returns: True if key is valid
     ///
     #[allow(dead_code)]
-    pub fn check_key(k: &String) -> bool {
+    pub fn check_key(k: &str) -> bool {
         regex::Regex::new(r"^[_a-zA-Z][_a-zA-Z0-9]*$")
             .unwrap()
             .is_match(k)
@@ -75,7 +167,7 @@ impl Store {
     /// Returns: True if value is valid
     ///
     #[allow(dead_code)]
-    pub fn check_value(v: &String) -> bool {
+    pub fn check_value(v: &str) -> bool {
         regex::Regex::new(r"^[^\n]*$")
             .unwrap()
             .is_match(v)
@@ -86,17 +178,54 @@ impl Store {
     /// Returns: Current driver instance
     ///
     #[allow(dead_code)]
-    pub fn driver(&self) -> &Driver {
+    pub fn driver(&self) -> &dyn Driver {
         &*self.driver
     }
 
-    /// Sets the driver.
+    /// Sets the driver. The store is considered dirty until it is next
+    /// loaded or saved as the new driver's content is unknown.
     ///
     /// * d: Driver to set
     ///
     #[allow(dead_code)]
-    pub fn driver_set(&mut self, d: Box<Driver>) {
+    pub fn driver_set(&mut self, d: Box<dyn Driver>) {
         self.driver = d;
+        self.synced = false;
+    }
+
+    /// Get the redaction applied whenever the store formats values.
+    ///
+    #[allow(dead_code)]
+    pub fn redact(&self) -> &Redact {
+        &self.redact
+    }
+
+    /// Sets the redaction applied whenever the store formats values.
+    ///
+    /// * r: Redaction to set
+    ///
+    #[allow(dead_code)]
+    pub fn redact_set(&mut self, r: Redact) {
+        self.redact = r;
+    }
+
+    /// Checks whether typed getters parse leniently.
+    ///
+    #[allow(dead_code)]
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Sets whether typed getters parse leniently, accepting radix
+    /// prefixes and underscores in integers and yes/no, on/off and 1/0
+    /// as bools, also within lists read by `get_vec`. Parsing is strict
+    /// by default.
+    ///
+    /// * l: True to parse leniently
+    ///
+    #[allow(dead_code)]
+    pub fn lenient_set(&mut self, l: bool) {
+        self.lenient = l;
     }
 
     /// Loads key value pairs from the driver clearing all current
@@ -106,7 +235,9 @@ impl Store {
     ///
     #[allow(dead_code)]
     pub fn load(&mut self) -> driver::IOCheck {
-        self.data = Store::_load(&*self.driver)?;
+        let (data, expiry) = Store::_split(Store::_load(&*self.driver)?)?;
+        self._replace(data, expiry)?;
+        self.synced = true;
         Ok(())
     }
 
@@ -118,196 +249,520 @@ impl Store {
     /// Returns: Empty result.
     ///
     #[allow(dead_code)]
-    pub fn load_via_driver(&mut self, d: &Driver) -> driver::IOCheck {
-        self.data = Store::_load(d)?;
+    pub fn load_via_driver(&mut self, d: &dyn Driver) -> driver::IOCheck {
+        let (data, expiry) = Store::_split(Store::_load(d)?)?;
+        self._replace(data, expiry)?;
+        self.synced = false;
         Ok(())
     }
 
-    /// Saves key value pairs via the driver.
+    /// Saves key value pairs via the driver while holding its lock.
+    /// Expired entries are purged first.
     ///
     /// Returns: Empty result.
     ///
     #[allow(dead_code)]
     pub fn save(&mut self) -> driver::IOCheck {
-        self.driver.save(&self.data)?;
-        Ok(())
+        self._locked(Store::_save)
     }
 
-    /// Saves key value pairs via a specified driver.
+    /// Saves key value pairs via the driver only if there are changes
+    /// since the last load or save. Only the changes are passed to the
+    /// driver unless the driver was replaced since.
+    ///
+    /// Returns: True if the store was saved.
+    ///
+    #[allow(dead_code)]
+    pub fn save_if_dirty(&mut self) -> io::Result<bool> {
+        self._purge();
+
+        if !self.is_dirty() {
+            return Ok(false);
+        }
+
+        self._locked(|s| {
+            if !s.synced {
+                return s._save();
+            }
+
+            let m = Store::_join(&s.data, &s.expiry);
+            let c = s._persisted_changes();
+            s.driver.save_changes(&m, &c)?;
+            s.changes.clear();
+            s.timed.clear();
+            Ok(())
+        })?;
+        Ok(true)
+    }
+
+    /// Checks whether there are changes since the last load or save.
+    ///
+    /// Returns: True if unsaved changes exist
+    ///
+    #[allow(dead_code)]
+    pub fn is_dirty(&self) -> bool {
+        !self.synced || !self.changes.is_empty()
+    }
+
+    /// Gets the keys added, changed or removed since the last load or
+    /// save.
+    ///
+    /// Returns: Changed keys
+    ///
+    #[allow(dead_code)]
+    pub fn changes(&self) -> &driver::Changes {
+        &self.changes
+    }
+
+    /// Saves key value pairs via a specified driver while holding its
+    /// lock. Expired entries are purged first.
     ///
     /// * d: Driver to load from.
     ///
     /// Returns: Empty result.
     ///
     #[allow(dead_code)]
-    pub fn save_via_driver(&mut self, d: &mut Driver) -> driver::IOCheck {
-        d.save(&self.data)?;
+    pub fn save_via_driver(&mut self, d: &mut dyn Driver) -> driver::IOCheck {
+        self._purge();
+        d.lock()?;
+        let r = d.save(&Store::_join(&self.data, &self.expiry));
+        let u = d.unlock();
+
+        r?;
+        u
+    }
+
+    /// Sets a value.
+    ///
+    /// * k: Key of the value.
+    /// * v: Value to set.
+    ///
+    /// Returns: Empty result, or an error if the key or value is
+    /// invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set(&mut self, k: &str, v: &str) -> driver::IOCheck {
+        Store::_check(k, v)?;
+        let old = self.data.insert(k.to_string(), v.to_string());
+        let had_ttl = self.expiry.remove(k).is_some();
+
+        if had_ttl {
+            self.timed.insert(k.to_string());
+        }
+        if had_ttl || old.as_deref() != Some(v) {
+            self._track(k, old.is_some(), true);
+        }
         Ok(())
     }
 
-    /// Gets a value as a string within an option.
+    /// Sets a value to a list of elements using the default list
+    /// format. Separators within elements are escaped.
+    ///
+    /// * k: Key of the value.
+    /// * items: Elements to set.
+    ///
+    /// Returns: Empty result, or an error if the key or value is
+    /// invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set_vec<T: fmt::Display>(&mut self, k: &str, items: &[T]) -> driver::IOCheck {
+        self.set_vec_with(k, items, &ListFormat::new())
+    }
+
+    /// Sets a value to a list of elements using a list format.
+    ///
+    /// * k: Key of the value.
+    /// * items: Elements to set.
+    /// * f: How the list is written.
+    ///
+    /// Returns: Empty result, or an error if the key or value is
+    /// invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set_vec_with<T: fmt::Display>(&mut self, k: &str, items: &[T], f: &ListFormat)
+        -> driver::IOCheck {
+        self.set(k, &f.join(items))
+    }
+
+    /// Sets a value to a duration written canonically with the largest
+    /// units first, e.g. '1h30m'.
+    ///
+    /// * k: Key of the value.
+    /// * d: Duration to set.
+    ///
+    /// Returns: Empty result, or an error if the key is invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set_duration(&mut self, k: &str, d: Duration) -> driver::IOCheck {
+        self.set(k, &units::format_duration(d))
+    }
+
+    /// Sets a value to a byte size written canonically with the
+    /// largest unit dividing it exactly, e.g. '10KiB'.
     ///
     /// * k: Key of the value.
+    /// * n: Number of bytes to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key is invalid.
     ///
     #[allow(dead_code)]
-    pub fn get(&self, k: &String) -> Option<&String> {
-        self.data.get(k)
+    pub fn set_byte_size(&mut self, k: &str, n: u64) -> driver::IOCheck {
+        self.set(k, &units::format_byte_size(n))
     }
 
-    /// Gets a value as a char within an option.
+    /// Sets a value to an IP address.
     ///
     /// * k: Key of the value.
+    /// * ip: Address to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key is invalid.
     ///
     #[allow(dead_code)]
-    pub fn get_char(&self, k: &String) -> Option<char> {
-        self.data.get(k).map_or(None, |v| v.chars().next())
+    pub fn set_ip(&mut self, k: &str, ip: IpAddr) -> driver::IOCheck {
+        self.set(k, &ip.to_string())
     }
 
-    /// Gets a value as an i8 within a number result.
+    /// Sets a value to a socket address, e.g. '127.0.0.1:8080' or
+    /// '[::1]:8080'.
     ///
     /// * k: Key of the value.
+    /// * a: Address to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key is invalid.
     ///
     #[allow(dead_code)]
-    pub fn get_i8(&self, k: &String) -> IntResult<i8> {
-        self.data.get(k).map(|v| v.parse::<i8>())
+    pub fn set_socket_addr(&mut self, k: &str, a: SocketAddr) -> driver::IOCheck {
+        self.set(k, &a.to_string())
     }
 
-    /// Gets a value as an u8 within a number result.
+    /// Sets a value to a path as it is, without collapsing the home
+    /// directory.
     ///
     /// * k: Key of the value.
+    /// * p: Path to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key is invalid or the
+    /// path is not valid UTF-8.
     ///
     #[allow(dead_code)]
-    pub fn get_u8(&self, k: &String) -> IntResult<u8> {
-        self.data.get(k).map(|v| v.parse::<u8>())
+    pub fn set_path<P: AsRef<Path>>(&mut self, k: &str, p: P) -> driver::IOCheck {
+        match p.as_ref().to_str() {
+            Some(v) => self.set(k, v),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path for '{}' is not valid UTF-8", k)))
+        }
     }
 
-    /// Gets a value as an i16 within a number result.
+    /// Sets a value that expires after some time. Once expired the
+    /// value is no longer visible to any getter and is purged on save.
     ///
     /// * k: Key of the value.
+    /// * v: Value to set.
+    /// * ttl: Time until the value expires. Times too far ahead to
+    ///   represent are clamped to the furthest expiry possible.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key or value is
+    /// invalid.
     ///
     #[allow(dead_code)]
-    pub fn get_i16(&self, k: &String) -> IntResult<i16> {
-        self.data.get(k).map(|v| v.parse::<i16>())
+    pub fn set_with_ttl(&mut self, k: &str, v: &str, ttl: Duration) -> driver::IOCheck {
+        Store::_check(k, v)?;
+        let old = self.data.insert(k.to_string(), v.to_string());
+        self.expiry.insert(k.to_string(), Store::_now().saturating_add(Store::_millis(ttl)));
+        self.timed.insert(k.to_string());
+        self._track(k, old.is_some(), true);
+        Ok(())
     }
 
-    /// Gets a value as an u16 within a number result.
+    /// Gets the time remaining before a value expires.
     ///
     /// * k: Key of the value.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Time remaining, or None if the value does not exist,
+    /// has already expired or never expires.
     ///
     #[allow(dead_code)]
-    pub fn get_u16(&self, k: &String) -> IntResult<u16> {
-        self.data.get(k).map(|v| v.parse::<u16>())
+    pub fn ttl(&self, k: &str) -> Option<Duration> {
+        let now = Store::_now();
+        self.expiry.get(k)
+            .filter(|&&t| t > now)
+            .map(|&t| Duration::from_millis(t - now))
     }
 
-    /// Gets a value as an i32 within a number result.
+    /// Removes a value.
     ///
     /// * k: Key of the value.
     ///
-    /// Returns: Value as a result.
+    /// Returns: The removed value if there was one.
     ///
     #[allow(dead_code)]
-    pub fn get_i32(&self, k: &String) -> IntResult<i32> {
-        self.data.get(k).map(|v| v.parse::<i32>())
+    pub fn remove(&mut self, k: &str) -> Option<String> {
+        let expired = self._is_expired(k);
+        if self.expiry.remove(k).is_some() {
+            self.timed.insert(k.to_string());
+        }
+        let old = self.data.remove(k);
+
+        if old.is_some() {
+            self._track(k, true, false);
+        }
+        old.filter(|_| !expired)
     }
 
-    /// Gets a value as an u32 within a number result.
+    /// Sets a value only if the current value matches the expected
+    /// one. The check is made against the latest state of the driver,
+    /// under the driver's lock, and a successful swap is saved straight
+    /// away. Other unsaved entries in the store are left untouched.
+    /// If the swap fails the store is left as it was, including any
+    /// unsaved value of the key, and the value observed is returned.
     ///
     /// * k: Key of the value.
+    /// * expected: Value expected, or None if the key should be absent.
+    /// * v: Value to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Whether the value was set and the value observed.
     ///
     #[allow(dead_code)]
-    pub fn get_u32(&self, k: &String) -> IntResult<u32> {
-        self.data.get(k).map(|v| v.parse::<u32>())
+    pub fn compare_and_set(&mut self, k: &str, expected: Option<&str>, v: &str) -> SwapResult {
+        self._swap(k, |old| {
+            if old == expected {
+                Some(v.to_string())
+            } else {
+                None
+            }
+        })
     }
 
-    /// Gets a value as an i64 within a number result.
+    /// Sets a value only if the key is absent from the latest state of
+    /// the driver. See `compare_and_set`.
     ///
     /// * k: Key of the value.
+    /// * v: Value to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Whether the value was set and the value observed.
     ///
     #[allow(dead_code)]
-    pub fn get_i64(&self, k: &String) -> IntResult<i64> {
-        self.data.get(k).map(|v| v.parse::<i64>())
+    pub fn set_if_absent(&mut self, k: &str, v: &str) -> SwapResult {
+        self.compare_and_set(k, None, v)
     }
 
-    /// Gets a value as an u64 within a number result.
+    /// Replaces a value with one computed from the latest value held by
+    /// the driver. See `compare_and_set`.
     ///
     /// * k: Key of the value.
+    /// * f: Given the current value, returns the new value or None to
+    ///   leave it unchanged.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Whether the value was set and the value observed.
     ///
     #[allow(dead_code)]
-    pub fn get_u64(&self, k: &String) -> IntResult<u64> {
-        self.data.get(k).map(|v| v.parse::<u64>())
+    pub fn update<F>(&mut self, k: &str, f: F) -> SwapResult
+        where F: FnOnce(Option<&str>) -> Option<String> {
+        self._swap(k, f)
     }
 
-    /// Gets a value as an f32 within a number result.
+    /// Gets a value as a string within an option. Sealed values are
+    /// decrypted when a key is configured.
+    ///
+    /// `get` keeps returning an option for compatibility, so a value
+    /// that is present but sealed without a key that decrypts it reads
+    /// as None, exactly like a missing key, and so do the typed getters
+    /// that read through `get`. Use `try_get` wherever sealed values
+    /// may be held: it reports them as a `Sealed` error.
     ///
     /// * k: Key of the value.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Value within an option, None if the key is missing,
+    /// expired or sealed and cannot be decrypted.
     ///
     #[allow(dead_code)]
-    pub fn get_f32(&self, k: &String) -> FloatResult<f32> {
-        self.data.get(k).map(|v| v.parse::<f32>())
+    pub fn get(&self, k: &str) -> Option<&String> {
+        self.try_get(k).ok().and_then(|v| v)
     }
 
-    /// Gets a value as an f64 within a number result.
+    /// Gets a value as a string within an option, failing if the value
+    /// is sealed and cannot be decrypted. This is the way to read
+    /// stores that may hold sealed values, see `get`.
     ///
     /// * k: Key of the value.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Value within an option, or a `Sealed` error.
     ///
     #[allow(dead_code)]
-    pub fn get_f64(&self, k: &String) -> FloatResult<f64> {
-        self.data.get(k).map(|v| v.parse::<f64>())
+    pub fn try_get(&self, k: &str) -> io::Result<Option<&String>> {
+        if self._is_expired(k) {
+            return Ok(None);
+        }
+
+        match self.data.get(k) {
+            Some(v) => self._reveal(k, v).map(Some),
+            None => Ok(None)
+        }
     }
 
-    /// Gets a value as an isize within a number result.
+    typed_getters!();
+}
+
+/// Implements setting dates and times, enabled by the 'chrono' cargo
+/// feature.
+///
+#[cfg(feature = "chrono")]
+impl Store {
+
+    /// Sets a value to a date and time written as RFC 3339, e.g.
+    /// '2024-05-01T12:30:00+02:00'.
+    ///
+    /// * k: Key of the value.
+    /// * d: Date and time to set.
+    ///
+    /// Returns: Empty result, or an error if the key is invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set_datetime<Tz: chrono::TimeZone>(&mut self, k: &str, d: &chrono::DateTime<Tz>)
+        -> driver::IOCheck where Tz::Offset: fmt::Display {
+        self.set(k, &d.to_rfc3339())
+    }
+
+    /// Sets a value to a date written as 'YYYY-MM-DD'.
     ///
     /// * k: Key of the value.
+    /// * d: Date to set.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key is invalid.
     ///
     #[allow(dead_code)]
-    pub fn get_isize(&self, k: &String) -> IntResult<isize> {
-        self.data.get(k).map(|v| v.parse::<isize>())
+    pub fn set_date(&mut self, k: &str, d: chrono::NaiveDate) -> driver::IOCheck {
+        self.set(k, &d.format("%Y-%m-%d").to_string())
     }
+}
 
-    /// Gets a value as an usize within a number result.
+/// Implements setting URLs, enabled by the 'url' cargo feature.
+///
+#[cfg(feature = "url")]
+impl Store {
+
+    /// Sets a value to a URL.
     ///
     /// * k: Key of the value.
+    /// * u: URL to set.
+    ///
+    /// Returns: Empty result, or an error if the key is invalid.
+    ///
+    #[allow(dead_code)]
+    pub fn set_url(&mut self, k: &str, u: &url::Url) -> driver::IOCheck {
+        self.set(k, u.as_str())
+    }
+}
+
+/// Implements sealing values within the store.
+///
+#[cfg(feature = "encryption")]
+impl Store {
+
+    /// Sets the key used to seal and unseal values, decrypting those
+    /// already held.
+    ///
+    /// * k: Key to use
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if a value does not decrypt.
     ///
     #[allow(dead_code)]
-    pub fn get_usize(&self, k: &String) -> IntResult<usize> {
-        self.data.get(k).map(|v| v.parse::<usize>())
+    pub fn secret_key_set(&mut self, k: Key) -> driver::IOCheck {
+        self.secret_provider_set(move || Ok(k.clone()))
     }
 
-    /// Gets a value as an bool within a number result.
+    /// Sets a function that supplies the key used to seal and unseal
+    /// values, decrypting those already held.
+    ///
+    /// * f: Supplies the key to use
+    ///
+    /// Returns: Empty result, or an error if a value does not decrypt.
+    ///
+    #[allow(dead_code)]
+    pub fn secret_provider_set<F>(&mut self, f: F) -> driver::IOCheck
+        where F: Fn() -> io::Result<Key> + 'static {
+        self.secrets.provider_set(Box::new(f));
+        self._unseal()
+    }
+
+    /// Sets a value sealed with the configured key so it is saved as
+    /// 'k=ENC[v1,...]' while `try_get` returns it decrypted.
     ///
     /// * k: Key of the value.
+    /// * v: Value to seal.
     ///
-    /// Returns: Value as a result.
+    /// Returns: Empty result, or an error if the key or value is
+    /// invalid or no key is configured.
     ///
     #[allow(dead_code)]
-    pub fn get_bool(&self, k: &String) -> BoolResult {
-        self.data.get(k).map(|v| v.parse::<bool>())
+    pub fn set_secret(&mut self, k: &str, v: &str) -> driver::IOCheck {
+        Store::_check(k, v)?;
+        let sealed = seal(&self.secrets.key()?, k, v);
+        self.set(k, &sealed)?;
+        self.secrets.remember(k, sealed, v.to_string());
+        Ok(())
+    }
+
+    /// Seals every sealed value again with a new key, which then
+    /// becomes the configured key. Save the store afterwards to keep
+    /// the new values.
+    ///
+    /// * k: New key
+    ///
+    /// Returns: Number of values sealed again, or an error if a value
+    /// does not decrypt with the current key.
+    ///
+    #[allow(dead_code)]
+    pub fn reseal(&mut self, k: Key) -> io::Result<usize> {
+        self._unseal()?;
+
+        let mut resealed = Vec::new();
+        for (key, v) in self.data.iter() {
+            if is_sealed(v) {
+                let plain = self.secrets.reveal(key, v)?.clone();
+                resealed.push((key.clone(), seal(&k, key, &plain), plain));
+            }
+        }
+
+        let n = resealed.len();
+        self.secrets.provider_set(Box::new(move || Ok(k.clone())));
+
+        for (key, sealed, plain) in resealed {
+            self.data.insert(key.clone(), sealed.clone());
+            self._track(&key, true, true);
+            self.secrets.remember(&key, sealed, plain);
+        }
+
+        Ok(n)
+    }
+}
+
+/// Implements debugging stores, printing unexpired pairs with values
+/// redacted as configured.
+///
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Store {{ data: ")?;
+        self.redact.debug_map(f, &self._visible())?;
+        write!(f, ", dirty: {} }}", self.is_dirty())
+    }
+}
+
+/// Implements displaying stores as 'k=v' lines, sorted by key, with
+/// values redacted as configured.
+///
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.redact.display_map(f, &self._visible())
+    }
+}
+
+/// Implements the default store.
+///
+impl Default for Store {
+    fn default() -> Store {
+        Store::new()
     }
 }
 
@@ -315,20 +770,326 @@ impl Store {
 ///
 impl Store {
 
+    /// Checks a key value pair is valid.
+    ///
+    #[allow(dead_code)]
+    fn _check(k: &str, v: &str) -> driver::IOCheck {
+
+        if !Store::check_key(k) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid key '{}'", k)))?
+        }
+
+        if k.starts_with(EXPIRY_PREFIX) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Key '{}' uses the reserved prefix '{}'", k, EXPIRY_PREFIX)))?
+        }
+
+        if !Store::check_value(v) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid value for '{}'", k)))?
+        }
+
+        Ok(())
+    }
+
+    /// Performs a conditional update while holding the driver's lock.
+    ///
+    #[allow(dead_code)]
+    fn _swap<F>(&mut self, k: &str, f: F) -> SwapResult
+        where F: FnOnce(Option<&str>) -> Option<String> {
+
+        if !Store::check_key(k) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid key '{}'", k)))?
+        }
+
+        self._locked(|s| s._swap_locked(k, f))
+    }
+
+    /// Runs an operation while holding the driver's lock, so saves and
+    /// conditional updates do not interleave with other writers.
+    ///
+    #[allow(dead_code)]
+    fn _locked<T, F>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Store) -> io::Result<T> {
+
+        self.driver.lock()?;
+        let r = f(self);
+        let u = self.driver.unlock();
+
+        let r = r?;
+        u?;
+        Ok(r)
+    }
+
+    /// Saves all key value pairs via the driver, which is locked by
+    /// the caller.
+    ///
+    #[allow(dead_code)]
+    fn _save(&mut self) -> driver::IOCheck {
+        self._purge();
+        self.driver.save(&Store::_join(&self.data, &self.expiry))?;
+        self.changes.clear();
+        self.timed.clear();
+        self.synced = true;
+        Ok(())
+    }
+
+    /// Reads the latest key value pairs from the driver, applies the
+    /// update to them and writes them back if it succeeded.
+    ///
+    #[allow(dead_code)]
+    fn _swap_locked<F>(&mut self, k: &str, f: F) -> SwapResult
+        where F: FnOnce(Option<&str>) -> Option<String> {
+
+        let (m, e) = Store::_split(Store::_load(&*self.driver)?)?;
+        let observed = match e.get(k) {
+            Some(&t) if t <= Store::_now() => None,
+            _ => m.get(k).cloned()
+        };
+
+        let v = match f(observed.as_deref()) {
+            Some(v) => v,
+            None => return Ok((false, observed))
+        };
+
+        Store::_check(k, &v)?;
+        let mut b = vec![driver::Op::Put(k.to_string(), v.clone())];
+        if e.contains_key(k) {
+            b.push(driver::Op::Delete(format!("{}{}", EXPIRY_PREFIX, k)));
+        }
+        self.driver.apply(&b)?;
+        self.data.insert(k.to_string(), v);
+        self.expiry.remove(k);
+        self.timed.remove(k);
+        self.changes.remove(k);
+
+        Ok((true, observed))
+    }
+
+    /// Replaces all entries with loaded ones. If a sealed value does
+    /// not decrypt the store is left as it was.
+    ///
+    /// * data: Loaded key value pairs.
+    /// * expiry: Loaded expiry times.
+    ///
+    fn _replace(&mut self, data: driver::Map, expiry: Expiry) -> driver::IOCheck {
+        let old = mem::replace(&mut self.data, data);
+        if let Err(e) = self._unseal() {
+            self.data = old;
+            return Err(e);
+        }
+
+        self.expiry = expiry;
+        self.timed.clear();
+        self.changes.clear();
+        Ok(())
+    }
+
+    /// Decrypts sealed values for `get`, keeping those already
+    /// decrypted unless every value decrypts.
+    ///
+    #[cfg(feature = "encryption")]
+    fn _unseal(&mut self) -> driver::IOCheck {
+        self.secrets.refresh(&self.data)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn _unseal(&mut self) -> driver::IOCheck {
+        Ok(())
+    }
+
+    /// Gets the value to lend out for a stored value, decrypting it if
+    /// it is sealed.
+    ///
+    #[cfg(feature = "encryption")]
+    fn _reveal<'a>(&'a self, k: &str, v: &'a String) -> io::Result<&'a String> {
+        self.secrets.reveal(k, v)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn _reveal<'a>(&'a self, _: &str, v: &'a String) -> io::Result<&'a String> {
+        Ok(v)
+    }
+
+    /// Unexpired key value pairs.
+    ///
+    #[allow(dead_code)]
+    fn _visible(&self) -> driver::Map {
+        self.data.iter()
+            .filter(|&(k, _)| !self._is_expired(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Current time in milliseconds since the Unix epoch.
+    ///
+    #[allow(dead_code)]
+    fn _now() -> u64 {
+        let d = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Store::_millis(d)
+    }
+
+    /// Converts a duration to whole milliseconds, saturating at the
+    /// largest number representable.
+    ///
+    #[allow(dead_code)]
+    fn _millis(d: Duration) -> u64 {
+        d.as_secs()
+            .checked_mul(1000)
+            .and_then(|ms| ms.checked_add(u64::from(d.subsec_millis())))
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Checks whether a key has an expiry time that has passed.
+    ///
+    #[allow(dead_code)]
+    fn _is_expired(&self, k: &str) -> bool {
+        self.expiry.get(k).is_some_and(|&t| t <= Store::_now())
+    }
+
+    /// Removes all expired entries.
+    ///
+    #[allow(dead_code)]
+    fn _purge(&mut self) {
+        let now = Store::_now();
+        let expired: Vec<String> = self.expiry.iter()
+            .filter(|&(_, &t)| t <= now)
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        for k in expired {
+            self.data.remove(&k);
+            self.expiry.remove(&k);
+            self.timed.insert(k.clone());
+            self._track(&k, true, false);
+        }
+    }
+
+    /// Records a change to a key given whether it existed before and
+    /// after the change. Keys added and removed again between saves
+    /// are forgotten.
+    ///
+    #[allow(dead_code)]
+    fn _track(&mut self, k: &str, existed: bool, exists: bool) {
+        let prior = self.changes.get(k).cloned();
+
+        let c = match (prior, existed, exists) {
+            (None, false, true) => Some(Change::Added),
+            (None, true, true) => Some(Change::Changed),
+            (None, true, false) => Some(Change::Removed),
+            (None, false, false) => None,
+            (Some(Change::Added), _, true) => Some(Change::Added),
+            (Some(Change::Added), _, false) => None,
+            (Some(_), _, true) => Some(Change::Changed),
+            (Some(_), _, false) => Some(Change::Removed)
+        };
+
+        match c {
+            Some(c) => self.changes.insert(k.to_string(), c),
+            None => self.changes.remove(k)
+        };
+    }
+
+    /// Changes as they are seen by a driver, including those to the
+    /// expiry companion keys of changed keys that had an expiry time
+    /// before the change or have one after it.
+    ///
+    #[allow(dead_code)]
+    fn _persisted_changes(&self) -> driver::Changes {
+        let mut out = driver::Changes::new();
+
+        for (k, &c) in self.changes.iter() {
+            out.insert(k.clone(), c);
+            if !self.timed.contains(k) {
+                continue;
+            }
+
+            let companion = match c {
+                Change::Removed => Change::Removed,
+                _ if self.expiry.contains_key(k) => Change::Changed,
+                _ => Change::Removed
+            };
+            out.insert(format!("{}{}", EXPIRY_PREFIX, k), companion);
+        }
+
+        out
+    }
+
+    /// Separates expiry companion keys from loaded key value pairs.
+    /// Companions without a matching entry are dropped.
+    ///
+    #[allow(dead_code)]
+    fn _split(mut m: driver::Map) -> io::Result<(driver::Map, Expiry)> {
+
+        let companions: Vec<String> = m.keys()
+            .filter(|k| k.starts_with(EXPIRY_PREFIX))
+            .cloned()
+            .collect();
+
+        let mut e = Expiry::new();
+        for c in companions {
+            let v = m.remove(&c).unwrap();
+            let t = v.parse::<u64>().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid expiry time for '{}'", c)))?;
+
+            let k = &c[EXPIRY_PREFIX.len()..];
+            if m.contains_key(k) {
+                e.insert(k.to_string(), t);
+            }
+        }
+
+        Ok((m, e))
+    }
+
+    /// Combines unexpired key value pairs with their expiry companion
+    /// keys ready for saving.
+    ///
+    #[allow(dead_code)]
+    fn _join(m: &driver::Map, e: &Expiry) -> driver::Map {
+        let now = Store::_now();
+        let mut out = driver::Map::new();
+
+        for (k, v) in m.iter() {
+            match e.get(k) {
+                Some(&t) if t <= now => continue,
+                Some(&t) => out.insert(format!("{}{}", EXPIRY_PREFIX, k), t.to_string()),
+                None => None
+            };
+            out.insert(k.clone(), v.clone());
+        }
+
+        out
+    }
+
     /// Loads key value pairs from a driver, checks them then
     /// returns them as a map.
     ///
     #[allow(dead_code)]
-    fn _load(d: &Driver) -> driver::IOResult {
+    fn _load(d: &dyn Driver) -> driver::IOResult {
 
         let s = d.load()?.clone();
 
+        if d.validated() {
+            return Ok(s);
+        }
+
         for (k, v) in s.iter() {
 
             if !Store::check_key(k) {
+                // Malformed keys may hold a whole line, secrets included,
+                // whatever the key looks like
                 Err::<(), _>(io::Error::new(
                     io::ErrorKind::InvalidData,
-                    format!("Invalid key '{}'", k)))?
+                    format!("Invalid key '{}'", REDACTED)))?
             }
 
             if !Store::check_value(v) {
@@ -346,11 +1107,12 @@ impl Store {
 macro_rules! assert_store {
     ( $s:expr, $k:expr, $v:ident ) => ({
         let store: &Store = $s;
+        let r = store.redact();
         match store.get($k) {
-            None => panic!("Expected '{:?}' but key '{:?}' not in store", $v, $k),
+            None => panic!("Expected '{:?}' but key '{:?}' not in store", r.value($k, $v), $k),
             Some(x) => {
                 if x != $v {
-                    panic!("Expected '{:?}' but found '{:?}'", $v, x);
+                    panic!("Expected '{:?}' but found '{:?}'", r.value($k, $v), r.value($k, x));
                 }
             }
         }
@@ -385,8 +1147,16 @@ macro_rules! string_vec {
 #[cfg(test)]
 mod tests {
 
+    use std::cell::RefCell;
+    use std::panic;
+    use std::rc::Rc;
+    use std::time::Duration;
+
     use Store;
+    use EXPIRY_PREFIX;
     use driver;
+    use driver::Change;
+    use redact::Redact;
 
     type MemDriver = driver::MemoryDriver;
 
@@ -399,7 +1169,7 @@ mod tests {
 
         for k in v.iter() {
             let r = Store::check_key(k);
-            assert!(r, format!("'{}' is a valid key but failed key checking", k));
+            assert!(r, "'{}' is a valid key but failed key checking", k);
         }
     }
 
@@ -412,7 +1182,7 @@ mod tests {
 
         for k in v.iter() {
             let r = Store::check_key(k);
-            assert!(!r, format!("'{}' is a valid key but failed key checking", k));
+            assert!(!r, "'{}' is a valid key but failed key checking", k);
         }
     }
 
@@ -425,7 +1195,7 @@ mod tests {
 
         for v in v.iter() {
             let r = Store::check_value(v);
-            assert!(r, format!("'{}' is a valid value but failed value checking", v));
+            assert!(r, "'{}' is a valid value but failed value checking", v);
         }
     }
 
@@ -438,7 +1208,7 @@ mod tests {
 
         for v in v.iter() {
             let r = Store::check_value(v);
-            assert!(!r, format!("'{}' is a valid value but failed value checking", v));
+            assert!(!r, "'{}' is a valid value but failed value checking", v);
         }
     }
 
@@ -826,4 +1596,510 @@ mod tests {
 
         assert_eq!(expected, actual, "Expected {} but actual is {}", expected, actual);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn set_and_remove() {
+
+        let mut s = Store::new();
+        s.set("a", "1").unwrap();
+        assert_eq!(Some(&str_of!("1")), s.get(&str_of!("a")));
+
+        assert!(s.set("1a", "1").is_err());
+        assert!(s.set("a", "1\n2").is_err());
+
+        assert_eq!(Some(str_of!("1")), s.remove("a"));
+        assert!(s.get(&str_of!("a")).is_none());
+    }
+
+    #[test]
+    fn compare_and_set() {
+
+        let mut s = init_store();
+
+        let (ok, seen) = s.compare_and_set("x", Some("abc"), "def").unwrap();
+        assert!(ok);
+        assert_eq!(Some(str_of!("abc")), seen);
+        assert_eq!(Some(&str_of!("def")), s.get(&str_of!("x")));
+
+        let (ok, seen) = s.compare_and_set("x", Some("abc"), "ghi").unwrap();
+        assert!(!ok);
+        assert_eq!(Some(str_of!("def")), seen);
+        assert_eq!(Some(&str_of!("def")), s.get(&str_of!("x")));
+
+        let m = s.driver().load().unwrap();
+        assert_eq!("def", m["x"]);
+    }
+
+    #[test]
+    fn set_if_absent() {
+
+        let mut s = init_store();
+
+        let (ok, seen) = s.set_if_absent("x", "def").unwrap();
+        assert!(!ok);
+        assert_eq!(Some(str_of!("abc")), seen);
+
+        let (ok, seen) = s.set_if_absent("new", "def").unwrap();
+        assert!(ok);
+        assert_eq!(None, seen);
+        assert_eq!(Some(&str_of!("def")), s.get(&str_of!("new")));
+    }
+
+    #[test]
+    fn update() {
+
+        let mut s = init_store();
+
+        let (ok, seen) = s.update("a", |v| {
+            v.map(|v| (v.parse::<i32>().unwrap() + 1).to_string())
+        }).unwrap();
+
+        assert!(ok);
+        assert_eq!(Some(str_of!("123")), seen);
+        assert_eq!(124, s.get_i32(&str_of!("a")).unwrap().unwrap());
+
+        let (ok, _) = s.update("a", |_| None).unwrap();
+        assert!(!ok);
+        assert_eq!(124, s.get_i32(&str_of!("a")).unwrap().unwrap());
+    }
+
+    #[test]
+    fn compare_and_set_sees_other_writers() {
+
+        let path = driver::test_dir("store_cas").join("store.kvs");
+
+        let mut a = Store::new();
+        a.driver_set(Box::new(driver::FileDriver::new(&path)));
+        a.set("z", "unsaved").unwrap();
+        a.set_if_absent("n", "1").unwrap();
+
+        let mut b = Store::new();
+        b.driver_set(Box::new(driver::FileDriver::new(&path)));
+        b.load().unwrap();
+        b.compare_and_set("n", Some("1"), "2").unwrap();
+
+        // a's view of n is stale but the swap checks the file
+        let (ok, seen) = a.compare_and_set("n", Some("1"), "3").unwrap();
+        assert!(!ok);
+        assert_eq!(Some(str_of!("2")), seen);
+        assert_eq!(Some(&str_of!("1")), a.get(&str_of!("n")));
+
+        // A failed swap keeps the caller's unsaved value
+        a.set("n", "local").unwrap();
+        let (ok, _) = a.compare_and_set("n", Some("1"), "3").unwrap();
+        assert!(!ok);
+        assert_eq!(Some(&str_of!("local")), a.get(&str_of!("n")));
+
+        // Unsaved entries are neither lost nor written
+        assert_eq!(Some(&str_of!("unsaved")), a.get(&str_of!("z")));
+        assert!(!a.driver().load().unwrap().contains_key("z"));
+    }
+
+    #[test]
+    fn save_waits_for_lock() {
+
+        let path = driver::test_dir("store_save_lock").join("store.kvs");
+
+        let mut s = Store::new();
+        let d = driver::FileDriver::new(&path).lock_timeout(Duration::from_millis(50));
+        s.driver_set(Box::new(d));
+        s.set("a", "1").unwrap();
+
+        let mut other = driver::file::LockFile::new(&path);
+        other.acquire().unwrap();
+        assert_eq!(::std::io::ErrorKind::TimedOut, s.save().unwrap_err().kind());
+        assert_eq!(::std::io::ErrorKind::TimedOut, s.save_if_dirty().unwrap_err().kind());
+
+        other.release().unwrap();
+        s.save().unwrap();
+        assert_eq!("1", s.driver().load().unwrap()["a"]);
+    }
+
+    #[test]
+    fn set_with_ttl_expires() {
+
+        let mut s = Store::new();
+        s.set_with_ttl("token", "abc", Duration::from_secs(3600)).unwrap();
+        s.set_with_ttl("stale", "123", Duration::from_millis(0)).unwrap();
+
+        assert_eq!(Some(&str_of!("abc")), s.get(&str_of!("token")));
+        assert!(s.ttl("token").unwrap() > Duration::from_secs(3500));
+
+        assert!(s.get(&str_of!("stale")).is_none());
+        assert!(s.get_i32(&str_of!("stale")).is_none());
+        assert!(s.ttl("stale").is_none());
+
+        s.set("token", "def").unwrap();
+        assert!(s.ttl("token").is_none());
+
+        assert!(s.set(&format!("{}x", EXPIRY_PREFIX), "1").is_err());
+
+        s.set_with_ttl("forever", "1", Duration::MAX).unwrap();
+        assert_eq!(Some(&str_of!("1")), s.get(&str_of!("forever")));
+        assert!(s.ttl("forever").unwrap() > Duration::from_secs(3600 * 24 * 365 * 1000));
+    }
+
+    #[test]
+    fn ttl_survives_save_load() {
+
+        let path = driver::test_dir("store_ttl").join("store.kvs");
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(driver::FileDriver::new(&path)));
+        s.set("plain", "1").unwrap();
+        s.set_with_ttl("token", "abc", Duration::from_secs(3600)).unwrap();
+        s.set_with_ttl("stale", "123", Duration::from_millis(0)).unwrap();
+        s.save().unwrap();
+
+        // Expired entries are purged and the expiry is a plain pair
+        let m = s.driver().load().unwrap();
+        assert_eq!(3, m.len());
+        assert!(m.contains_key(&format!("{}token", EXPIRY_PREFIX)));
+        assert!(!m.contains_key("stale"));
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(driver::FileDriver::new(&path)));
+        s.load().unwrap();
+
+        assert_eq!(Some(&str_of!("abc")), s.get(&str_of!("token")));
+        assert!(s.ttl("token").is_some());
+        assert!(s.ttl("plain").is_none());
+        assert!(s.get(&format!("{}token", EXPIRY_PREFIX)).is_none());
+    }
+
+    #[test]
+    fn changes_are_tracked() {
+
+        let mut s = init_store();
+        assert!(!s.is_dirty());
+
+        s.set("x", "abc").unwrap();
+        assert!(!s.is_dirty(), "Setting an equal value is not a change");
+
+        s.set("x", "def").unwrap();
+        s.set("new", "1").unwrap();
+        s.remove("y");
+        s.set("gone", "1").unwrap();
+        s.remove("gone");
+
+        let c = s.changes();
+        assert_eq!(3, c.len());
+        assert_eq!(Some(&Change::Changed), c.get("x"));
+        assert_eq!(Some(&Change::Added), c.get("new"));
+        assert_eq!(Some(&Change::Removed), c.get("y"));
+
+        s.save().unwrap();
+        assert!(!s.is_dirty());
+        assert!(s.changes().is_empty());
+    }
+
+    /// Records which save method was called on it.
+    struct SpyDriver {
+        inner: MemDriver,
+        calls: Rc<RefCell<Vec<String>>>
+    }
+
+    impl driver::Driver for SpyDriver {
+        fn load(&self) -> driver::IOResult {
+            self.inner.load()
+        }
+
+        fn save(&mut self, s: &driver::Map) -> driver::IOCheck {
+            self.calls.borrow_mut().push(str_of!("save"));
+            self.inner.save(s)
+        }
+
+        fn save_changes(&mut self, s: &driver::Map, c: &driver::Changes) -> driver::IOCheck {
+            let mut keys: Vec<&String> = c.keys().collect();
+            keys.sort();
+            self.calls.borrow_mut().push(format!("save_changes {:?}", keys));
+            self.inner.save_changes(s, c)
+        }
+    }
+
+    #[test]
+    fn save_if_dirty_passes_changes() {
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let d = SpyDriver {
+            inner: MemDriver::from(make_test_map()),
+            calls: calls.clone()
+        };
+
+        // A new driver's content is unknown so everything is saved
+        let mut s = Store::from(make_test_map());
+        s.driver_set(Box::new(d));
+        assert!(s.save_if_dirty().unwrap());
+        assert!(!s.save_if_dirty().unwrap());
+
+        s.set("x", "def").unwrap();
+        s.remove("y");
+        assert!(s.save_if_dirty().unwrap());
+        assert!(!s.is_dirty());
+
+        // Companions only change for keys with an expiry time
+        s.set_with_ttl("t", "1", Duration::from_secs(3600)).unwrap();
+        assert!(s.save_if_dirty().unwrap());
+        s.set("t", "2").unwrap();
+        assert!(s.save_if_dirty().unwrap());
+
+        let companion = format!("{}t", EXPIRY_PREFIX);
+        let expected = vec![
+            str_of!("save"),
+            format!("save_changes {:?}", [str_of!("x"), str_of!("y")]),
+            format!("save_changes {:?}", [companion.clone(), str_of!("t")]),
+            format!("save_changes {:?}", [companion, str_of!("t")])];
+        assert_eq!(expected, *calls.borrow());
+
+        let m = s.driver().load().unwrap();
+        assert_eq!("def", m["x"]);
+        assert!(!m.contains_key("y"));
+        assert_eq!("123", m["a"]);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn secrets_are_sealed() {
+        use driver::{Driver, FileDriver};
+        use driver::encrypted::{EncryptionError, Key};
+
+        let path = driver::test_dir("store_secrets").join("store.txt");
+        let (old, new) = (Key::generate(), Key::generate());
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(FileDriver::new(&path)));
+        assert!(s.set_secret("api_token", "s3cr3t").is_err());
+
+        s.secret_key_set(old.clone()).unwrap();
+        s.set_secret("api_token", "s3cr3t").unwrap();
+        s.set("user", "Rincewind").unwrap();
+        assert_eq!("s3cr3t", s.get(&str_of!("api_token")).unwrap());
+        s.save().unwrap();
+
+        let m = FileDriver::new(&path).load().unwrap();
+        assert!(m["api_token"].starts_with("ENC[v1,"), "{}", m["api_token"]);
+        assert_eq!("Rincewind", m["user"]);
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(FileDriver::new(&path)));
+        s.load().unwrap();
+        assert_eq!(None, s.get(&str_of!("api_token")));
+        assert_eq!(None, s.get_char("api_token"));
+        let e = s.try_get("api_token").unwrap_err();
+        assert_eq!(Some(&EncryptionError::Sealed(str_of!("api_token"))), EncryptionError::of(&e));
+        assert_eq!("Rincewind", s.try_get("user").unwrap().unwrap());
+
+        s.secret_key_set(old.clone()).unwrap();
+        assert_eq!("s3cr3t", s.try_get("api_token").unwrap().unwrap());
+
+        assert_eq!(1, s.reseal(new.clone()).unwrap());
+        assert_eq!("s3cr3t", s.get(&str_of!("api_token")).unwrap());
+        s.save().unwrap();
+
+        let mut s = Store::new();
+        s.driver_set(Box::new(FileDriver::new(&path)));
+        s.set("kept", "1").unwrap();
+        s.secret_key_set(old).unwrap();
+        assert!(s.load().is_err());
+        assert_eq!("1", s.get("kept").unwrap());
+        assert_eq!(None, s.get("user"));
+        s.secret_key_set(new).unwrap();
+        s.load().unwrap();
+        assert_eq!("s3cr3t", s.get(&str_of!("api_token")).unwrap());
+    }
+
+    #[test]
+    fn formats_redacted() {
+        let mut s = Store::new();
+        s.set("user", "Rincewind").unwrap();
+        s.set("api_token", "abc123").unwrap();
+        s.set("Client_Secret", "xyz").unwrap();
+
+        let debug = format!("{:?}", s);
+        assert!(!debug.contains("abc123") && !debug.contains("xyz"), "{}", debug);
+        assert!(debug.contains("\"user\": \"Rincewind\""), "{}", debug);
+        assert!(debug.ends_with(", dirty: true }"), "{}", debug);
+
+        assert_eq!("Client_Secret=[REDACTED]\napi_token=[REDACTED]\nuser=Rincewind\n", s.to_string());
+
+        s.redact_set(Redact::patterns(&["user"]));
+        assert_eq!("Client_Secret=xyz\napi_token=abc123\nuser=[REDACTED]\n", s.to_string());
+    }
+
+    #[test]
+    fn errors_are_redacted() {
+        let mut m = driver::Map::new();
+        m.insert(str_of!("db password hunter2"), str_of!("x"));
+
+        let mut s = Store::new();
+        let e = s.load_via_driver(&MemDriver::from(m)).unwrap_err();
+        assert!(!e.to_string().contains("hunter2"), "{}", e);
+
+        let mut m = driver::Map::new();
+        m.insert(str_of!("hunter2 x"), str_of!("x"));
+        let e = s.load_via_driver(&MemDriver::from(m)).unwrap_err();
+        assert!(!e.to_string().contains("hunter2"), "{}", e);
+
+        let mut s = Store::new();
+        s.set("api_token", "abc123").unwrap();
+        let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let expected = &str_of!("wrong");
+            assert_store!(&s, &str_of!("api_token"), expected);
+        }));
+        let e = r.unwrap_err();
+        let msg = e.downcast_ref::<String>().unwrap();
+        assert!(!msg.contains("abc123") && !msg.contains("wrong"), "{}", msg);
+    }
+
+    #[test]
+    fn set_and_get_vec() {
+        use list::{Empty, ListError, ListFormat};
+
+        let mut s = Store::new();
+        s.set_vec("hosts", &["a.local", "b,c", " d "]).unwrap();
+        assert_eq!("a.local,b\\,c,\\ d\\ ", s.get(&str_of!("hosts")).unwrap());
+        assert_eq!(vec!["a.local", "b,c", " d "],
+                   s.get_vec::<String>(&str_of!("hosts")).unwrap().unwrap());
+
+        s.set("ports", "80, 443,8080").unwrap();
+        assert_eq!(vec![80, 443, 8080], s.get_vec::<u16>(&str_of!("ports")).unwrap().unwrap());
+
+        s.set("ports", "80,http,8080").unwrap();
+        match s.get_vec::<u16>(&str_of!("ports")).unwrap() {
+            Err(ListError::Parse(1, _)) => (),
+            r => panic!("Unexpected {:?}", r)
+        }
+
+        let f = ListFormat::new().separator(':').empty(Empty::Error);
+        s.set_vec_with("path", &["/bin", "/usr/bin"], &f).unwrap();
+        assert_eq!("/bin:/usr/bin", s.get(&str_of!("path")).unwrap());
+        s.set("path", "/bin::/usr/bin").unwrap();
+        assert_eq!(Some(Err(ListError::Empty(1))), s.get_vec_with::<String>(&str_of!("path"), &f));
+
+        assert_eq!(None, s.get_vec::<u8>(&str_of!("missing")));
+    }
+
+    #[test]
+    fn set_and_get_units() {
+        use units::UnitError;
+
+        let mut s = Store::new();
+        s.set_duration("timeout", Duration::from_secs(5400)).unwrap();
+        assert_eq!("1h30m", s.get(&str_of!("timeout")).unwrap());
+        assert_eq!(Some(Ok(Duration::from_secs(5400))), s.get_duration(&str_of!("timeout")));
+
+        s.set("timeout", "250ms").unwrap();
+        assert_eq!(Some(Ok(Duration::from_millis(250))), s.get_duration(&str_of!("timeout")));
+        s.set("timeout", "5 fortnights").unwrap();
+        assert_eq!(Some(Err(UnitError::UnknownUnit(str_of!("fortnights")))),
+                   s.get_duration(&str_of!("timeout")));
+
+        s.set_byte_size("cache", 10 << 10).unwrap();
+        assert_eq!("10KiB", s.get(&str_of!("cache")).unwrap());
+        assert_eq!(Some(Ok(10 << 10)), s.get_byte_size(&str_of!("cache")));
+
+        s.set("cache", "2GiB").unwrap();
+        assert_eq!(Some(Ok(2 << 30)), s.get_byte_size(&str_of!("cache")));
+        s.set("cache", "20EiB").unwrap();
+        assert_eq!(Some(Err(UnitError::Overflow)), s.get_byte_size(&str_of!("cache")));
+
+        assert_eq!(None, s.get_duration(&str_of!("missing")));
+        assert_eq!(None, s.get_byte_size(&str_of!("missing")));
+    }
+
+    #[test]
+    fn lenient_parsing() {
+        let mut s = Store::new();
+        s.set("mode", "0o755").unwrap();
+        s.set("limit", "1_000_000").unwrap();
+        s.set("debug", "yes").unwrap();
+
+        assert!(!s.lenient());
+        assert!(s.get_u32(&str_of!("mode")).unwrap().is_err());
+        assert!(s.get_u64(&str_of!("limit")).unwrap().is_err());
+        assert!(s.get_bool(&str_of!("debug")).unwrap().is_err());
+
+        s.lenient_set(true);
+        assert_eq!(Some(Ok(0o755)), s.get_u32(&str_of!("mode")));
+        assert_eq!(Some(Ok(1_000_000)), s.get_u64(&str_of!("limit")));
+        assert_eq!(Some(Ok(true)), s.get_bool(&str_of!("debug")));
+        assert_eq!(None, s.get_bool(&str_of!("missing")));
+
+        s.set("modes", "0o755,0o644").unwrap();
+        s.set("flags", "yes,no").unwrap();
+        assert_eq!(vec![0o755, 0o644], s.get_vec::<u32>("modes").unwrap().unwrap());
+        assert_eq!(vec![true, false], s.get_vec::<bool>("flags").unwrap().unwrap());
+
+        s.lenient_set(false);
+        assert!(s.get_vec::<u32>("modes").unwrap().is_err());
+        assert!(s.get_vec::<bool>("flags").unwrap().is_err());
+    }
+
+    #[test]
+    fn set_and_get_addresses() {
+        use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+        use std::path::PathBuf;
+
+        let mut s = Store::new();
+        s.set_ip("host", IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap();
+        assert_eq!("::1", s.get(&str_of!("host")).unwrap());
+        assert_eq!(Some(Ok(IpAddr::V6(Ipv6Addr::LOCALHOST))), s.get_ip(&str_of!("host")));
+        s.set("host", "10.0.0.256").unwrap();
+        assert!(s.get_ip(&str_of!("host")).unwrap().is_err());
+
+        let a: SocketAddr = "[::1]:8080".parse().unwrap();
+        s.set_socket_addr("listen", a).unwrap();
+        assert_eq!("[::1]:8080", s.get(&str_of!("listen")).unwrap());
+        assert_eq!(Some(Ok(a)), s.get_socket_addr(&str_of!("listen")));
+        s.set("listen", "localhost").unwrap();
+        assert!(s.get_socket_addr(&str_of!("listen")).unwrap().is_err());
+
+        s.set_path("logs", "/var/log/app").unwrap();
+        assert_eq!(Some(Ok(PathBuf::from("/var/log/app"))), s.get_path(&str_of!("logs")));
+
+        if let Some(home) = std::env::var_os("HOME") {
+            s.set("logs", "~/logs").unwrap();
+            assert_eq!(Some(Ok(PathBuf::from(home).join("logs"))), s.get_path(&str_of!("logs")));
+        }
+
+        assert_eq!(None, s.get_ip(&str_of!("missing")));
+        assert_eq!(None, s.get_path(&str_of!("missing")));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn set_and_get_dates() {
+        use chrono::{FixedOffset, NaiveDate, TimeZone, Utc};
+
+        let mut s = Store::new();
+        let d = FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap();
+        s.set_datetime("started", &d).unwrap();
+        assert_eq!("2024-05-01T12:30:00+02:00", s.get(&str_of!("started")).unwrap());
+        assert_eq!(Some(Ok(d)), s.get_datetime(&str_of!("started")));
+
+        s.set_datetime("started", &Utc.with_ymd_and_hms(2024, 5, 1, 10, 30, 0).unwrap()).unwrap();
+        assert_eq!(d, s.get_datetime(&str_of!("started")).unwrap().unwrap());
+        s.set("started", "2024-05-01 12:30").unwrap();
+        assert!(s.get_datetime(&str_of!("started")).unwrap().is_err());
+
+        let day = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        s.set_date("due", day).unwrap();
+        assert_eq!("2024-02-29", s.get(&str_of!("due")).unwrap());
+        assert_eq!(Some(Ok(day)), s.get_date(&str_of!("due")));
+        s.set("due", "2023-02-29").unwrap();
+        assert!(s.get_date(&str_of!("due")).unwrap().is_err());
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn set_and_get_urls() {
+        let mut s = Store::new();
+        let u = url::Url::parse("https://example.com/api?v=2").unwrap();
+        s.set_url("endpoint", &u).unwrap();
+        assert_eq!("https://example.com/api?v=2", s.get(&str_of!("endpoint")).unwrap());
+        assert_eq!(Some(Ok(u)), s.get_url(&str_of!("endpoint")));
+
+        s.set("endpoint", "not a url").unwrap();
+        assert_eq!(Some(Err(url::ParseError::RelativeUrlWithoutBase)), s.get_url(&str_of!("endpoint")));
+    }
+}
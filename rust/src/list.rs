@@ -0,0 +1,361 @@
+//!
+//! # List values
+//!
+//! Splits values holding lists, such as 'hosts=a,b,c', into elements
+//! and joins elements back into values. Within a value a '\' escapes
+//! the character after it so elements may hold the separator, e.g.
+//! 'a\,b,c' holds 'a,b' and 'c'.
+//!
+//! Elements are read into any type implementing `Element`. Integers
+//! and bools follow the lenient parsing of the typed getters, other
+//! types are read by their `FromStr` and implement it with an empty
+//! `impl`.
+//!
+
+use std::error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, FixedOffset, NaiveDate};
+#[cfg(feature = "url")]
+use url::Url;
+
+use getters::{parse_bool, parse_int};
+
+/// Result of getting a list: none if the key is absent, otherwise the
+/// parsed elements or the first element that failed.
+///
+pub type VecResult<T> = Option<Result<Vec<T>, ListError<<T as FromStr>::Err>>>;
+
+/// Types list elements can be read into.
+///
+pub trait Element: FromStr {
+
+    /// Parses an element, by its `FromStr` unless overridden.
+    ///
+    /// * e: Element to parse
+    /// * lenient: Whether parsing is lenient
+    ///
+    /// Returns: Element, or the error of its parser
+    ///
+    fn parse_element(e: &str, lenient: bool) -> Result<Self, Self::Err> {
+        let _ = lenient;
+        e.parse()
+    }
+}
+
+macro_rules! int_elements {
+    ($($t:ty)*) => {
+        $(
+            impl Element for $t {
+                fn parse_element(e: &str, lenient: bool) -> Result<$t, Self::Err> {
+                    parse_int::<$t>(e, lenient)
+                }
+            }
+        )*
+    }
+}
+
+int_elements!(i8 u8 i16 u16 i32 u32 i64 u64 isize usize);
+
+impl Element for bool {
+    fn parse_element(e: &str, lenient: bool) -> Result<bool, Self::Err> {
+        parse_bool(e, lenient)
+    }
+}
+
+macro_rules! plain_elements {
+    ($($t:ty)*) => {
+        $(
+            impl Element for $t {}
+        )*
+    }
+}
+
+plain_elements!(String char f32 f64 i128 u128 PathBuf);
+plain_elements!(IpAddr Ipv4Addr Ipv6Addr SocketAddr SocketAddrV4 SocketAddrV6);
+
+#[cfg(feature = "chrono")]
+plain_elements!(DateTime<FixedOffset> NaiveDate);
+#[cfg(feature = "url")]
+plain_elements!(Url);
+
+/// How empty elements, e.g. the middle of 'a,,b', are handled. A value
+/// that is empty altogether is always an empty list.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Empty {
+    Keep,
+    Skip,
+    Error
+}
+
+/// How lists are written within values.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListFormat {
+    separator: char,
+    trim: bool,
+    empty: Empty
+}
+
+/// An element of a list that could not be read.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListError<E> {
+
+    /// The element at an index failed to parse.
+    Parse(usize, E),
+
+    /// The element at an index is empty and empty elements are
+    /// refused.
+    Empty(usize)
+}
+
+/// Implements factory methods.
+///
+impl ListFormat {
+
+    /// Creates the default list format: elements are separated by ',',
+    /// whitespace around them is trimmed and empty ones are skipped.
+    ///
+    pub fn new() -> ListFormat {
+        ListFormat {
+            separator: ',',
+            trim: true,
+            empty: Empty::Skip
+        }
+    }
+
+    /// Sets the separator between elements. It may not be '\'.
+    ///
+    /// * c: Separator
+    ///
+    pub fn separator(mut self, c: char) -> ListFormat {
+        assert!(c != '\\', "'\\' cannot separate list elements");
+        self.separator = c;
+        self
+    }
+
+    /// Sets whether unescaped whitespace around elements is removed.
+    ///
+    /// * t: True to trim elements
+    ///
+    pub fn trim(mut self, t: bool) -> ListFormat {
+        self.trim = t;
+        self
+    }
+
+    /// Sets how empty elements are handled.
+    ///
+    /// * e: Empty element policy
+    ///
+    pub fn empty(mut self, e: Empty) -> ListFormat {
+        self.empty = e;
+        self
+    }
+
+    /// Splits a value into its elements, removing escapes.
+    ///
+    /// * v: Value holding a list
+    ///
+    /// Returns: Elements, or the index of an empty element if they are
+    /// refused
+    ///
+    pub fn split(&self, v: &str) -> Result<Vec<String>, usize> {
+        if v.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Characters of each element paired with whether they were
+        // escaped, so escaped whitespace survives trimming
+        let mut raw: Vec<Vec<(char, bool)>> = vec![Vec::new()];
+        let mut chars = v.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let e = chars.next().unwrap_or('\\');
+                    raw.last_mut().unwrap().push((e, true));
+                }
+                c if c == self.separator => raw.push(Vec::new()),
+                c => raw.last_mut().unwrap().push((c, false))
+            }
+        }
+
+        let mut out = Vec::new();
+        for (i, mut e) in raw.into_iter().enumerate() {
+            if self.trim {
+                let start = e.iter().position(|&(c, esc)| esc || !c.is_whitespace()).unwrap_or(e.len());
+                let end = e.iter().rposition(|&(c, esc)| esc || !c.is_whitespace()).map_or(start, |n| n + 1);
+                e = e[start..end].to_vec();
+            }
+
+            if e.is_empty() {
+                match self.empty {
+                    Empty::Keep => (),
+                    Empty::Skip => continue,
+                    Empty::Error => return Err(i)
+                }
+            }
+
+            out.push(e.into_iter().map(|(c, _)| c).collect());
+        }
+
+        Ok(out)
+    }
+
+    /// Parses a value into typed elements.
+    ///
+    /// * v: Value holding a list
+    ///
+    /// Returns: Elements, or the first element that could not be read
+    ///
+    pub fn parse<T: FromStr>(&self, v: &str) -> Result<Vec<T>, ListError<T::Err>> {
+        self.parse_with(v, |e| e.parse::<T>())
+    }
+
+    /// Parses a value into typed elements, leniently if asked as the
+    /// typed getters do.
+    ///
+    /// * v: Value holding a list
+    /// * lenient: Whether elements are parsed leniently
+    ///
+    /// Returns: Elements, or the first element that could not be read
+    ///
+    pub fn parse_lenient<T: Element>(&self, v: &str, lenient: bool)
+        -> Result<Vec<T>, ListError<T::Err>> {
+        self.parse_with(v, |e| T::parse_element(e, lenient))
+    }
+
+    /// Parses each element of a value with a function.
+    ///
+    fn parse_with<T, E, P>(&self, v: &str, p: P) -> Result<Vec<T>, ListError<E>>
+        where P: Fn(&str) -> Result<T, E> {
+        let elements = self.split(v).map_err(ListError::Empty)?;
+        elements.iter()
+            .enumerate()
+            .map(|(i, e)| p(e).map_err(|err| ListError::Parse(i, err)))
+            .collect()
+    }
+
+    /// Joins elements into a value, escaping characters that would
+    /// otherwise be read differently.
+    ///
+    /// * items: Elements to join
+    ///
+    /// Returns: Value holding the list
+    ///
+    pub fn join<T: fmt::Display>(&self, items: &[T]) -> String {
+        let sep = self.separator.to_string();
+        items.iter()
+            .map(|item| self.escape(&item.to_string()))
+            .collect::<Vec<String>>()
+            .join(&sep)
+    }
+
+    /// Escapes an element.
+    ///
+    fn escape(&self, e: &str) -> String {
+        let n = e.chars().count();
+        let mut out = String::new();
+
+        for (i, c) in e.chars().enumerate() {
+            let edge = i == 0 || i + 1 == n;
+            if c == '\\' || c == self.separator || (self.trim && edge && c.is_whitespace()) {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+
+        out
+    }
+}
+
+/// Implements the default list format.
+///
+impl Default for ListFormat {
+    fn default() -> ListFormat {
+        ListFormat::new()
+    }
+}
+
+/// Implements list error methods.
+///
+impl<E> ListError<E> {
+
+    /// Gets the index of the element that could not be read.
+    ///
+    pub fn index(&self) -> usize {
+        match *self {
+            ListError::Parse(i, _) | ListError::Empty(i) => i
+        }
+    }
+}
+
+/// Implements displaying list errors.
+///
+impl<E: fmt::Display> fmt::Display for ListError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ListError::Parse(i, ref e) => write!(f, "Invalid list element {}: {}", i, e),
+            ListError::Empty(i) => write!(f, "Empty list element {}", i)
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> error::Error for ListError<E> {}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn split_with_options() {
+        let f = ListFormat::new();
+        assert_eq!(vec!["a", "b c", "d"], f.split(" a , b c,,d, ").unwrap());
+        assert_eq!(Vec::<String>::new(), f.split("").unwrap());
+        assert_eq!(vec!["a,b", "c\\", " d"], f.split("a\\,b,c\\\\,\\ d").unwrap());
+
+        let f = ListFormat::new().separator(';').trim(false).empty(Empty::Keep);
+        assert_eq!(vec![" a", "", "b,c "], f.split(" a;;b,c ").unwrap());
+
+        let f = ListFormat::new().empty(Empty::Error);
+        assert_eq!(Err(2), f.split("a,b, ,c"));
+    }
+
+    #[test]
+    fn parse_reports_index() {
+        let f = ListFormat::new();
+        assert_eq!(vec![1, 2, 3], f.parse::<u8>("1, 2, 3").unwrap());
+
+        let e = f.parse::<u8>("1,2,300").unwrap_err();
+        assert_eq!(2, e.index());
+        assert!(e.to_string().starts_with("Invalid list element 2:"), "{}", e);
+
+        assert_eq!(vec![16, 1000], f.parse_lenient::<u16>("0x10, 1_000", true).unwrap());
+        assert_eq!(0, f.parse_lenient::<u16>("0x10, 1_000", false).unwrap_err().index());
+        assert_eq!(vec![true, false], f.parse_lenient::<bool>("on,0", true).unwrap());
+        assert_eq!(vec![1.5], f.parse_lenient::<f64>("1.5", true).unwrap());
+    }
+
+    #[test]
+    fn join_round_trips() {
+        for f in &[ListFormat::new(), ListFormat::new().separator(' ').trim(false)] {
+            let items = vec!["plain", "a,b", " padded ", "back\\slash", "x y"];
+            let v = f.join(&items);
+            assert_eq!(items, f.split(&v).unwrap(), "{}", v);
+        }
+        assert_eq!("1,2,3", ListFormat::new().join(&[1, 2, 3]));
+    }
+}
@@ -0,0 +1,331 @@
+//!
+//! # Memory mapped store
+//!
+//! A read only store for very large datasets. Key value pairs are
+//! written once to a sorted index file which is then memory mapped so
+//! lookups binary search the file in place without loading or copying
+//! it. All integers are little endian:
+//!
+//! magic     4 bytes  "RCKI"
+//! version   u16      1
+//! reserved  u16      0
+//! count     u64      number of entries
+//! offsets   u64      per entry, in key order: offset of the entry
+//! entries            per entry: u32 key length, key bytes,
+//!                    u32 value length, value bytes (UTF-8)
+//!
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+
+use memmap2::Mmap;
+
+use driver::Map;
+use driver::file::write_atomic;
+use Store;
+
+/// Identifies an index file.
+///
+pub const MAGIC: &[u8; 4] = b"RCKI";
+
+/// Version of the index layout written.
+///
+pub const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 16;
+
+/// A read only store backed by a memory mapped index file.
+///
+pub struct MappedStore {
+    map: Mmap,
+    count: usize,
+    lenient: bool
+}
+
+/// Implements public mapped store methods.
+///
+impl MappedStore {
+
+    /// Memory maps an index file, checking its layout once so that
+    /// lookups need not.
+    ///
+    /// * path: Path of the index file
+    ///
+    /// Returns: The store
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedStore> {
+        let f = fs::File::open(path)?;
+
+        // The file must not be modified while mapped, index files are
+        // only ever replaced whole by `write`
+        let map = unsafe { Mmap::map(&f)? };
+
+        let count = check(&map)?;
+        Ok(MappedStore { map, count, lenient: false })
+    }
+
+    /// Writes key value pairs to an index file, replacing it
+    /// atomically.
+    ///
+    /// * path: Path of the index file
+    /// * m: Key value pairs
+    ///
+    /// Returns: Empty result, or an error if a pair is invalid
+    ///
+    pub fn write<P: AsRef<Path>>(path: P, m: &Map) -> io::Result<()> {
+        let mut keys: Vec<&String> = m.keys().collect();
+        keys.sort();
+
+        let mut b = Vec::new();
+        b.extend_from_slice(MAGIC);
+        b.extend_from_slice(&VERSION.to_le_bytes());
+        b.extend_from_slice(&0u16.to_le_bytes());
+        b.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+
+        let mut entries = Vec::new();
+        let base = HEADER_LEN + keys.len() * 8;
+
+        for k in keys {
+            let v = &m[k];
+
+            if !Store::check_key(k) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid key '{}'", k)));
+            }
+
+            if !Store::check_value(v) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid value for '{}'", k)));
+            }
+
+            b.extend_from_slice(&((base + entries.len()) as u64).to_le_bytes());
+            entries.extend_from_slice(&(k.len() as u32).to_le_bytes());
+            entries.extend_from_slice(k.as_bytes());
+            entries.extend_from_slice(&(v.len() as u32).to_le_bytes());
+            entries.extend_from_slice(v.as_bytes());
+        }
+
+        b.extend_from_slice(&entries);
+        write_atomic(path.as_ref(), &b)
+    }
+
+    /// Gets the number of entries.
+    ///
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks whether there are no entries.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Checks whether typed getters parse leniently.
+    ///
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Sets whether typed getters parse leniently, as for `Store`.
+    ///
+    /// * l: True to parse leniently
+    ///
+    pub fn lenient_set(&mut self, l: bool) {
+        self.lenient = l;
+    }
+
+    /// Gets a value borrowed from the mapped file.
+    ///
+    /// * k: Key of the value.
+    ///
+    /// Returns: Value as a string slice within an option.
+    ///
+    pub fn get(&self, k: &str) -> Option<&str> {
+        let k = k.as_bytes();
+        let (mut lo, mut hi) = (0, self.count);
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (key, value) = entry(&self.map, mid);
+
+            match key.cmp(k) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                // Checked again as the file may be rewritten while mapped
+                Ordering::Equal => return str::from_utf8(value).ok()
+            }
+        }
+
+        None
+    }
+
+    typed_getters!();
+}
+
+/// Reads a little endian u32.
+///
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+/// Reads a little endian u64.
+///
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    let mut x = [0u8; 8];
+    x.copy_from_slice(&b[at..at + 8]);
+    u64::from_le_bytes(x)
+}
+
+/// Gets the key and value bytes of an entry. Only valid once the index
+/// has been checked.
+///
+fn entry(b: &[u8], i: usize) -> (&[u8], &[u8]) {
+    let at = u64_at(b, HEADER_LEN + i * 8) as usize;
+    let klen = u32_at(b, at) as usize;
+    let key = &b[at + 4..at + 4 + klen];
+    let vat = at + 4 + klen;
+    let vlen = u32_at(b, vat) as usize;
+    (key, &b[vat + 4..vat + 4 + vlen])
+}
+
+/// Checks the layout of an index: every entry lies within the file,
+/// holds UTF-8 and keys are in strictly ascending order.
+///
+/// Returns: Number of entries
+///
+fn check(b: &[u8]) -> io::Result<usize> {
+    let bad = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    if b.len() < HEADER_LEN || &b[..4] != MAGIC {
+        return Err(bad("Not an index file"));
+    }
+
+    if u16::from_le_bytes([b[4], b[5]]) != VERSION {
+        return Err(bad("Unsupported index file version"));
+    }
+
+    let count = u64_at(b, 8) as usize;
+    if count > (b.len() - HEADER_LEN) / 8 {
+        return Err(bad("Index file is truncated"));
+    }
+
+    let mut prev: Option<&[u8]> = None;
+    for i in 0..count {
+        let at = u64_at(b, HEADER_LEN + i * 8) as usize;
+
+        let fits = |at: usize| at.checked_add(4).is_some_and(|end| end <= b.len());
+        if !fits(at) {
+            return Err(bad("Index file is truncated"));
+        }
+
+        let vat = at + 4 + u32_at(b, at) as usize;
+        if !fits(vat) || vat + 4 + u32_at(b, vat) as usize > b.len() {
+            return Err(bad("Index file is truncated"));
+        }
+
+        let (key, value) = entry(b, i);
+        if str::from_utf8(key).is_err() || str::from_utf8(value).is_err() {
+            return Err(bad("Index file holds invalid UTF-8"));
+        }
+
+        if prev.is_some_and(|p| p >= key) {
+            return Err(bad("Index file keys are not sorted"));
+        }
+        prev = Some(key);
+    }
+
+    Ok(count)
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use driver::Map;
+    use driver::test_dir;
+    use super::MappedStore;
+
+    fn make_map() -> Map {
+        let mut m = Map::new();
+        for i in 0..1000 {
+            m.insert(format!("key_{}", i), i.to_string());
+        }
+        m.insert("flag".to_string(), "true".to_string());
+        m.insert("ratio".to_string(), "-4.5".to_string());
+        m.insert("name".to_string(), "caf\u{e9}".to_string());
+        m
+    }
+
+    #[test]
+    fn write_open_get() {
+        let path = test_dir("mapped_get").join("store.idx");
+        MappedStore::write(&path, &make_map()).unwrap();
+
+        let s = MappedStore::open(&path).unwrap();
+        assert_eq!(1003, s.len());
+
+        for i in 0..1000 {
+            assert_eq!(Some(&*i.to_string()), s.get(&format!("key_{}", i)));
+        }
+        assert_eq!(Some("caf\u{e9}"), s.get("name"));
+        assert_eq!(None, s.get("key_1000"));
+        assert_eq!(None, s.get(""));
+    }
+
+    #[test]
+    fn typed_getters() {
+        let path = test_dir("mapped_typed").join("store.idx");
+        MappedStore::write(&path, &make_map()).unwrap();
+
+        let s = MappedStore::open(&path).unwrap();
+        assert_eq!(999, s.get_u16("key_999").unwrap().unwrap());
+        assert_eq!(-4.5, s.get_f64("ratio").unwrap().unwrap());
+        assert!(s.get_bool("flag").unwrap().unwrap());
+        assert_eq!(Some('c'), s.get_char("name"));
+        assert!(s.get_i8("key_999").unwrap().is_err());
+
+        let mut s = s;
+        s.lenient_set(true);
+        assert_eq!(Some(Ok(999)), s.get_u16("key_999"));
+    }
+
+    #[test]
+    fn empty_index() {
+        let path = test_dir("mapped_empty").join("store.idx");
+        MappedStore::write(&path, &Map::new()).unwrap();
+
+        let s = MappedStore::open(&path).unwrap();
+        assert!(s.is_empty());
+        assert_eq!(None, s.get("a"));
+    }
+
+    #[test]
+    fn corrupt_index_is_rejected() {
+        let path = test_dir("mapped_corrupt").join("store.idx");
+        MappedStore::write(&path, &make_map()).unwrap();
+
+        let b = fs::read(&path).unwrap();
+        fs::write(&path, &b[..b.len() - 1]).unwrap();
+        assert!(MappedStore::open(&path).is_err());
+
+        fs::write(&path, b"a=1\n").unwrap();
+        assert!(MappedStore::open(&path).is_err());
+
+        let mut m = Map::new();
+        m.insert("1a".to_string(), "x".to_string());
+        assert!(MappedStore::write(&path, &m).is_err());
+    }
+}
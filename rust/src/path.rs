@@ -0,0 +1,134 @@
+//!
+//! # Path values
+//!
+//! Reads paths from values, expanding the home directory so that
+//! '~/logs', '$HOME/logs' and '${HOME}/logs' all name the 'logs'
+//! directory within it. A '~' only expands at the start of a value and
+//! only on its own or before a '/', so '~user' is left as it is.
+//!
+
+use std::env;
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Result of getting a path.
+///
+pub type PathResult = Option<Result<PathBuf, PathError>>;
+
+/// Why a path could not be read.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+
+    /// The value refers to the home directory but 'HOME' is not set.
+    NoHome
+}
+
+/// Implements displaying path errors.
+///
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::NoHome => write!(f, "Cannot expand home directory: HOME is not set")
+        }
+    }
+}
+
+impl error::Error for PathError {}
+
+/// Expands the home directory within a value using the 'HOME'
+/// environment variable.
+///
+/// * v: Value holding a path
+///
+/// Returns: Path
+///
+pub fn expand(v: &str) -> Result<PathBuf, PathError> {
+    expand_with(v, env::var_os("HOME"))
+}
+
+/// Expands the home directory within a value.
+///
+/// * v: Value holding a path
+/// * home: Home directory, if known
+///
+/// Returns: Path
+///
+pub fn expand_with(v: &str, home: Option<OsString>) -> Result<PathBuf, PathError> {
+    let home = || home.clone().ok_or(PathError::NoHome);
+    let mut out = OsString::new();
+    let mut rest = v;
+
+    if rest == "~" || rest.starts_with("~/") {
+        out.push(home()?);
+        rest = &rest[1..];
+    }
+
+    while let Some(i) = rest.find('$') {
+        out.push(&rest[..i]);
+        rest = &rest[i..];
+
+        let len = if rest.starts_with("${HOME}") {
+            7
+        } else if rest.starts_with("$HOME") && !rest[5..].starts_with(|c: char| c == '_' || c.is_ascii_alphanumeric()) {
+            5
+        } else {
+            0
+        };
+
+        if len == 0 {
+            out.push("$");
+            rest = &rest[1..];
+        } else {
+            out.push(home()?);
+            rest = &rest[len..];
+        }
+    }
+
+    out.push(rest);
+    Ok(PathBuf::from(out))
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn expands_home() {
+        let home = || Some(OsString::from("/home/rincewind"));
+        let cases = [
+            ("~", "/home/rincewind"),
+            ("~/logs", "/home/rincewind/logs"),
+            ("$HOME/logs", "/home/rincewind/logs"),
+            ("${HOME}/logs", "/home/rincewind/logs"),
+            ("/srv/$HOME", "/srv//home/rincewind"),
+            ("~user/logs", "~user/logs"),
+            ("/tmp/~", "/tmp/~"),
+            ("$HOMEDIR/$", "$HOMEDIR/$"),
+            ("/var/log", "/var/log")
+        ];
+
+        for &(v, p) in cases.iter() {
+            assert_eq!(Ok(PathBuf::from(p)), expand_with(v, home()), "{}", v);
+        }
+    }
+
+    #[test]
+    fn home_must_be_set() {
+        assert_eq!(Err(PathError::NoHome), expand_with("~/logs", None));
+        assert_eq!(Err(PathError::NoHome), expand_with("${HOME}", None));
+        assert_eq!(Ok(PathBuf::from("/var/log")), expand_with("/var/log", None));
+    }
+}
@@ -0,0 +1,179 @@
+//!
+//! # Redaction
+//!
+//! Hides values whose keys look secret wherever the crate formats
+//! them, e.g. within `Debug` output, `Display` output and errors. Keys
+//! are matched case insensitively against glob patterns where '*'
+//! matches any run of characters.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Replaces redacted values.
+///
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Patterns used unless others are set.
+///
+pub const DEFAULT_PATTERNS: [&str; 3] = ["*password*", "*_token", "*secret*"];
+
+/// Decides which values are redacted by their keys.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redact {
+    patterns: Vec<String>
+}
+
+/// Implements factory methods.
+///
+impl Redact {
+
+    /// Creates a redaction using the default patterns.
+    ///
+    pub fn new() -> Redact {
+        Redact::patterns(&DEFAULT_PATTERNS)
+    }
+
+    /// Creates a redaction using some patterns.
+    ///
+    /// * p: Glob patterns of keys whose values are redacted
+    ///
+    pub fn patterns(p: &[&str]) -> Redact {
+        Redact {
+            patterns: p.iter().map(|p| p.to_lowercase()).collect()
+        }
+    }
+
+    /// Creates a redaction that hides nothing.
+    ///
+    pub fn none() -> Redact {
+        Redact::patterns(&[])
+    }
+
+    /// Checks whether the value of a key is redacted.
+    ///
+    /// * k: Key to check
+    ///
+    pub fn matches(&self, k: &str) -> bool {
+        let k = k.to_lowercase();
+        self.patterns.iter().any(|p| glob(p.as_bytes(), k.as_bytes()))
+    }
+
+    /// Gets a value as it may be shown.
+    ///
+    /// * k: Key of the value
+    /// * v: Value
+    ///
+    /// Returns: The value, or `REDACTED` if its key matches
+    ///
+    pub fn value<'a>(&self, k: &str, v: &'a str) -> &'a str {
+        if self.matches(k) {
+            REDACTED
+        } else {
+            v
+        }
+    }
+
+    /// Formats key value pairs as a debug map, sorted by key, with
+    /// matching values redacted.
+    ///
+    /// * f: Formatter to write to
+    /// * m: Key value pairs
+    ///
+    pub fn debug_map(&self, f: &mut fmt::Formatter, m: &HashMap<String, String>) -> fmt::Result {
+        let mut keys: Vec<&String> = m.keys().collect();
+        keys.sort();
+        f.debug_map()
+            .entries(keys.into_iter().map(|k| (k, self.value(k, &m[k]))))
+            .finish()
+    }
+
+    /// Formats key value pairs as 'k=v' lines, sorted by key, with
+    /// matching values redacted.
+    ///
+    /// * f: Formatter to write to
+    /// * m: Key value pairs
+    ///
+    pub fn display_map(&self, f: &mut fmt::Formatter, m: &HashMap<String, String>) -> fmt::Result {
+        let mut keys: Vec<&String> = m.keys().collect();
+        keys.sort();
+        for k in keys {
+            writeln!(f, "{}={}", k, self.value(k, &m[k]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Implements the default redaction.
+///
+impl Default for Redact {
+    fn default() -> Redact {
+        Redact::new()
+    }
+}
+
+/// Matches text against a glob pattern where '*' matches any run of
+/// characters.
+///
+fn glob(p: &[u8], s: &[u8]) -> bool {
+    let (mut pi, mut si) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == s[si] {
+            pi += 1;
+            si += 1;
+        } else if let Some((sp, ss)) = star {
+            pi = sp + 1;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn default_patterns() {
+        let r = Redact::new();
+        for k in &["password", "DB_PASSWORD_2", "api_token", "client_secret", "SECRET"] {
+            assert!(r.matches(k), "{} should match", k);
+        }
+        for k in &["user", "token", "tokens_used", "pass"] {
+            assert!(!r.matches(k), "{} should not match", k);
+        }
+
+        assert_eq!(REDACTED, r.value("api_token", "abc"));
+        assert_eq!("abc", r.value("user", "abc"));
+        assert!(!Redact::none().matches("password"));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let r = Redact::patterns(&["key", "a*b*c", "*x"]);
+        assert!(r.matches("KEY"));
+        assert!(!r.matches("keys"));
+        assert!(r.matches("abc"));
+        assert!(r.matches("a_b_b_c"));
+        assert!(!r.matches("a_b_c_d"));
+        assert!(r.matches("x"));
+        assert!(r.matches("max"));
+    }
+}
@@ -0,0 +1,109 @@
+//!
+//! # Sealed values
+//!
+//! Keeps the decrypted form of values sealed with
+//! `driver::encrypted::seal` so the store can lend them out. Enabled by
+//! the 'encryption' cargo feature.
+//!
+
+use std::collections::HashMap;
+use std::io;
+
+use driver::{IOCheck, Map};
+use driver::encrypted::{is_sealed, unseal, EncryptionError, Key, KeyProvider};
+
+/// The key used to seal values and the values it has decrypted.
+///
+pub struct Secrets {
+    key: Option<KeyProvider>,
+    plain: HashMap<String, (String, String)>
+}
+
+/// Implements secrets methods.
+///
+impl Secrets {
+
+    /// Creates secrets without a key.
+    ///
+    pub fn new() -> Secrets {
+        Secrets {
+            key: None,
+            plain: HashMap::new()
+        }
+    }
+
+    /// Sets the key provider.
+    ///
+    /// * f: Supplies the key to seal and unseal with
+    ///
+    pub fn provider_set(&mut self, f: KeyProvider) {
+        self.key = Some(f);
+        self.plain.clear();
+    }
+
+    /// Gets the current key.
+    ///
+    /// Returns: Key, or an error if none is configured
+    ///
+    pub fn key(&self) -> io::Result<Key> {
+        match self.key {
+            Some(ref f) => f(),
+            None => Err(EncryptionError::Key("No key is configured".to_string()).into())
+        }
+    }
+
+    /// Decrypts every sealed value not already decrypted and forgets
+    /// those that are gone. Without a key nothing is decrypted. If a
+    /// value does not decrypt the values already decrypted are kept
+    /// as they were.
+    ///
+    /// * data: All key value pairs of the store
+    ///
+    pub fn refresh(&mut self, data: &Map) -> IOCheck {
+        let keys = match self.key {
+            Some(_) => vec![self.key()?],
+            None => Vec::new()
+        };
+
+        let mut plain = HashMap::new();
+        for (k, v) in data.iter().filter(|&(_, v)| is_sealed(v)) {
+            let p = match self.plain.get(k) {
+                Some((sealed, p)) if sealed == v => p.clone(),
+                _ if keys.is_empty() => continue,
+                _ => unseal(&keys, k, v)?
+            };
+            plain.insert(k.clone(), (v.clone(), p));
+        }
+
+        self.plain = plain;
+        Ok(())
+    }
+
+    /// Remembers the decrypted form of a value just sealed.
+    ///
+    /// * k: Key of the value
+    /// * sealed: Sealed value
+    /// * plain: Decrypted value
+    ///
+    pub fn remember(&mut self, k: &str, sealed: String, plain: String) {
+        self.plain.insert(k.to_string(), (sealed, plain));
+    }
+
+    /// Reveals a value, decrypted if it is sealed.
+    ///
+    /// * k: Key of the value
+    /// * v: Value as held by the store
+    ///
+    /// Returns: Value, or a `Sealed` error if it cannot be decrypted
+    ///
+    pub fn reveal<'a>(&'a self, k: &str, v: &'a String) -> io::Result<&'a String> {
+        if !is_sealed(v) {
+            return Ok(v);
+        }
+
+        match self.plain.get(k) {
+            Some((sealed, plain)) if sealed == v => Ok(plain),
+            _ => Err(EncryptionError::Sealed(k.to_string()).into())
+        }
+    }
+}
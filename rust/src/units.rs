@@ -0,0 +1,267 @@
+//!
+//! # Units
+//!
+//! Reads and writes durations and byte sizes with human friendly
+//! units:
+//!
+//! Durations   '250ms', '30s', '5m', '1h30m', units d h m s ms us ns
+//! Byte sizes  '512', '10KB', '10KiB', '2GiB', decimal units KB MB GB
+//!             TB PB EB and binary units KiB MiB GiB TiB PiB EiB
+//!
+//! Units are case insensitive for byte sizes, apart from telling 'KiB'
+//! from 'KB', and exact for durations where 'm' and 'ms' differ.
+//!
+
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+/// Result of getting a duration.
+///
+pub type DurationResult = Option<Result<Duration, UnitError>>;
+
+/// Result of getting a byte size.
+///
+pub type ByteSizeResult = Option<Result<u64, UnitError>>;
+
+/// Why a duration or byte size could not be read.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitError {
+
+    /// The value is not a number followed by a unit.
+    Invalid(String),
+
+    /// A unit is not known.
+    UnknownUnit(String),
+
+    /// The value is too large to represent.
+    Overflow
+}
+
+/// Implements displaying unit errors.
+///
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnitError::Invalid(ref v) => write!(f, "Invalid quantity '{}'", v),
+            UnitError::UnknownUnit(ref u) => write!(f, "Unknown unit '{}'", u),
+            UnitError::Overflow => write!(f, "Quantity is too large")
+        }
+    }
+}
+
+impl error::Error for UnitError {}
+
+const DURATION_UNITS: [(&str, u128); 8] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("\u{b5}s", 1_000),
+    ("ns", 1)
+];
+
+const BYTE_UNITS: [(&str, u64); 13] = [
+    ("EiB", 1 << 60),
+    ("EB", 1_000_000_000_000_000_000),
+    ("PiB", 1 << 50),
+    ("PB", 1_000_000_000_000_000),
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+    ("B", 1)
+];
+
+/// Parses a duration made of one or more whole numbers each followed
+/// by a unit, e.g. '1h30m' or '1h 30m'.
+///
+/// * s: Text to parse
+///
+/// Returns: Duration
+///
+pub fn parse_duration(s: &str) -> Result<Duration, UnitError> {
+    let invalid = || UnitError::Invalid(s.to_string());
+    let text = s.trim();
+    if text.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit_len = rest[digits..].find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len() - digits);
+        if digits == 0 {
+            return Err(invalid());
+        }
+
+        let n: u128 = rest[..digits].parse().map_err(|_| UnitError::Overflow)?;
+        let unit = rest[digits..digits + unit_len].trim();
+        let scale = DURATION_UNITS.iter()
+            .find(|&&(u, _)| u == unit)
+            .map(|&(_, ns)| ns)
+            .ok_or_else(|| if unit.is_empty() { invalid() } else { UnitError::UnknownUnit(unit.to_string()) })?;
+
+        total = n.checked_mul(scale)
+            .and_then(|x| total.checked_add(x))
+            .ok_or(UnitError::Overflow)?;
+        rest = &rest[digits + unit_len..];
+    }
+
+    let secs = total / 1_000_000_000;
+    if secs > u128::from(u64::MAX) {
+        return Err(UnitError::Overflow);
+    }
+    Ok(Duration::new(secs as u64, (total % 1_000_000_000) as u32))
+}
+
+/// Formats a duration canonically using the largest units first, e.g.
+/// 5400 seconds as '1h30m'.
+///
+/// * d: Duration to format
+///
+/// Returns: Text
+///
+pub fn format_duration(d: Duration) -> String {
+    let mut ns = d.as_nanos();
+    if ns == 0 {
+        return "0s".to_string();
+    }
+
+    let mut out = String::new();
+    for &(unit, scale) in DURATION_UNITS.iter().filter(|&&(u, _)| u != "\u{b5}s") {
+        if ns >= scale {
+            out.push_str(&format!("{}{}", ns / scale, unit));
+            ns %= scale;
+        }
+    }
+    out
+}
+
+/// Parses a byte size: a whole number optionally followed by a unit.
+///
+/// * s: Text to parse
+///
+/// Returns: Number of bytes
+///
+pub fn parse_byte_size(s: &str) -> Result<u64, UnitError> {
+    let text = s.trim();
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    if digits == 0 {
+        return Err(UnitError::Invalid(s.to_string()));
+    }
+
+    let n: u64 = text[..digits].parse().map_err(|_| UnitError::Overflow)?;
+    let unit = text[digits..].trim_start();
+    if unit.is_empty() {
+        return Ok(n);
+    }
+
+    let scale = BYTE_UNITS.iter()
+        .find(|&&(u, _)| u.eq_ignore_ascii_case(unit))
+        .map(|&(_, b)| b)
+        .ok_or_else(|| UnitError::UnknownUnit(unit.to_string()))?;
+
+    n.checked_mul(scale).ok_or(UnitError::Overflow)
+}
+
+/// Formats a byte size canonically using the largest unit dividing it
+/// exactly, preferring binary units, e.g. 10240 as '10KiB'. Sizes no
+/// unit divides are written as a plain number.
+///
+/// * n: Number of bytes
+///
+/// Returns: Text
+///
+pub fn format_byte_size(n: u64) -> String {
+    BYTE_UNITS.iter()
+        .find(|&&(u, b)| u != "B" && n != 0 && n.is_multiple_of(b))
+        .map_or_else(|| n.to_string(), |&(u, b)| format!("{}{}", n / b, u))
+}
+
+//
+// ...end of source code!
+//
+// Beginning of tests...
+//
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let cases = [
+            ("250ms", Duration::from_millis(250)),
+            ("30s", Duration::from_secs(30)),
+            ("5m", Duration::from_secs(300)),
+            ("1h30m", Duration::from_secs(5400)),
+            ("2d", Duration::from_secs(172_800)),
+            ("1s500ms", Duration::from_millis(1500)),
+            ("7us", Duration::from_micros(7)),
+            ("0s", Duration::from_secs(0))
+        ];
+
+        for &(text, d) in cases.iter() {
+            assert_eq!(Ok(d), parse_duration(text), "{}", text);
+            assert_eq!(text, format_duration(d));
+        }
+
+        assert_eq!(Ok(Duration::from_micros(3)), parse_duration("3\u{b5}s"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("1m 30s"));
+        assert_eq!("1m30s", format_duration(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn duration_errors() {
+        assert_eq!(Err(UnitError::UnknownUnit("y".to_string())), parse_duration("3y"));
+        assert_eq!(Err(UnitError::Invalid("30".to_string())), parse_duration("30"));
+        assert_eq!(Err(UnitError::Invalid("".to_string())), parse_duration(""));
+        assert_eq!(Err(UnitError::Invalid("h1".to_string())), parse_duration("h1"));
+        assert_eq!(Err(UnitError::Overflow), parse_duration("99999999999999999999999d"));
+        assert_eq!(Err(UnitError::Overflow), parse_duration("999999999999999d"));
+    }
+
+    #[test]
+    fn byte_sizes() {
+        let cases = [
+            ("512", 512),
+            ("10KB", 10_000),
+            ("10KiB", 10_240),
+            ("2GiB", 2 << 30),
+            ("1EiB", 1 << 60),
+            ("1500", 1500),
+            ("0", 0)
+        ];
+
+        for &(text, n) in cases.iter() {
+            assert_eq!(Ok(n), parse_byte_size(text), "{}", text);
+            assert_eq!(text, format_byte_size(n));
+        }
+
+        assert_eq!(Ok(4096), parse_byte_size("4 kib"));
+        assert_eq!(Ok(3), parse_byte_size("3B"));
+        assert_eq!("1MB", format_byte_size(1_000_000));
+    }
+
+    #[test]
+    fn byte_size_errors() {
+        assert_eq!(Err(UnitError::UnknownUnit("XB".to_string())), parse_byte_size("1XB"));
+        assert_eq!(Err(UnitError::Invalid("KB".to_string())), parse_byte_size("KB"));
+        assert_eq!(Err(UnitError::Overflow), parse_byte_size("16EiB"));
+        assert_eq!(Err(UnitError::Overflow), parse_byte_size("99999999999999999999"));
+        assert_eq!(Err(UnitError::UnknownUnit("-1".to_string())), parse_byte_size("1-1"));
+    }
+}